@@ -7,8 +7,6 @@ use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::json_types::{ValidAccountId, U128};
 use near_sdk::{env, AccountId, Balance, StorageUsage};
 
-use crate::errors::*;
-
 // USAGE UNIT
 const U128_STORAGE: StorageUsage = 16;
 const U64_STORAGE: StorageUsage = 8;
@@ -30,10 +28,38 @@ const ACC_ID_AS_CLT_KEY_STORAGE: StorageUsage = ACC_ID_AS_KEY_STORAGE + 1;
 pub const INIT_ACCOUNT_STORAGE: StorageUsage =
     ACC_ID_AS_CLT_KEY_STORAGE + 1 + U128_STORAGE + U32_STORAGE + U64_STORAGE;
 
+/// Versioned account record, so fields are added to `Account` without rewriting all the accounts.
+/// To add fields, keep the current layout as `AccountV<n>` converting itself with `into_current`,
+/// add `V<n>(AccountV<n>)` before `Current` and grow `INIT_ACCOUNT_STORAGE` by the new fields.
+/// Accounts are upgraded when read and stored in the new layout on their next save.
+/// Accounts of the first version are untagged `AccountV1` records, kept in `legacy_accounts`.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub enum VAccount {
+    Current(Account),
+}
+
+impl VAccount {
+    /// Upgrades account to the current version.
+    pub fn into_current(self) -> Account {
+        match self {
+            VAccount::Current(account) => account,
+        }
+    }
+}
+
+impl From<Account> for VAccount {
+    fn from(account: Account) -> Self {
+        VAccount::Current(account)
+    }
+}
+
 #[derive(BorshSerialize, BorshDeserialize)]
 pub struct Account {
     pub near_amount: Balance,
     pub tokens: UnorderedMap<AccountId, Balance>,
+    /// Storage of the record itself the account is charged for, `INIT_ACCOUNT_STORAGE` at the time
+    /// it was last reconciled. Accounts upgraded to a larger layout keep the smaller one
+    /// until their deposit covers the larger one, so upgrades never lock them.
     pub storage_used: StorageUsage,
 }
 
@@ -44,16 +70,12 @@ impl Account {
             tokens: UnorderedMap::new(StorageKey::AccountTokens {
                 account_id: account_id.clone(),
             }),
-            storage_used: 0,
+            storage_used: INIT_ACCOUNT_STORAGE,
         }
     }
 
     pub fn get_balance(&self, token_id: &AccountId) -> Option<Balance> {
-        if let Some(token_balance) = self.tokens.get(token_id) {
-            Some(token_balance)
-        } else {
-            None
-        }
+        self.tokens.get(token_id)
     }
 
     /// Returns minimal account deposit storage usage possible.
@@ -93,20 +115,27 @@ impl Account {
     }
 
     pub fn storage_usage(&self) -> Balance {
-        (INIT_ACCOUNT_STORAGE
-            + self.tokens.len() as u64 * (KEY_PREFIX_ACC + ACC_ID_AS_KEY_STORAGE + U128_STORAGE))
+        (self.storage_used
+            + self.tokens.len() * (KEY_PREFIX_ACC + ACC_ID_AS_KEY_STORAGE + U128_STORAGE))
             as u128
             * env::storage_byte_cost()
     }
 
+    /// Charges storage of the record grown by upgrades, if the deposit covers it.
+    fn reconcile_storage_usage(&mut self) {
+        if self.storage_used < INIT_ACCOUNT_STORAGE {
+            let storage_used = self.storage_used;
+            self.storage_used = INIT_ACCOUNT_STORAGE;
+            if self.storage_usage() > self.near_amount {
+                self.storage_used = storage_used;
+            }
+        }
+    }
+
     pub fn storage_available(&self) -> Balance {
         // [AUDIT_01] avoid math overflow
         let locked = self.storage_usage();
-        if self.near_amount > locked {
-            self.near_amount - locked
-        } else {
-            0
-        }
+        self.near_amount.saturating_sub(locked)
     }
 
     /// Deposit amount to the balance of given token,
@@ -194,9 +223,42 @@ impl Contract {
             account.unregister(&token_id);
         }
         self.internal_save_account(&sender_id, account);
+        Event::Withdraw {
+            account_id: &sender_id,
+            token_id: &token_id,
+            amount: U128(amount),
+        }
+        .emit();
+        self.internal_send_tokens(&sender_id, &token_id, amount)
+    }
+
+    /// Sends all lostfound tokens of given token to the predecessor.
+    /// If the transfer fails again, they are credited to its deposits or kept as lostfound.
+    #[payable]
+    pub fn claim_lostfound(&mut self, token_id: ValidAccountId) -> Promise {
+        assert_one_yocto();
+        let token_id: AccountId = token_id.into();
+        let sender_id = env::predecessor_account_id();
+        let amount = self
+            .lostfound
+            .remove(&(sender_id.clone(), token_id.clone()))
+            .expect(ERR140_NO_LOSTFOUND);
         self.internal_send_tokens(&sender_id, &token_id, amount)
     }
 
+    /// Returns lostfound tokens of given account claimable with `claim_lostfound`,
+    /// of the tokens whitelisted now.
+    pub fn get_lostfound(&self, account_id: ValidAccountId) -> HashMap<AccountId, U128> {
+        self.whitelisted_tokens
+            .iter()
+            .filter_map(|token_id| {
+                self.lostfound
+                    .get(&(account_id.to_string(), token_id.clone()))
+                    .map(|amount| (token_id, U128(amount)))
+            })
+            .collect()
+    }
+
     #[private]
     pub fn exchange_callback_post_withdraw(
         &mut self,
@@ -215,38 +277,13 @@ impl Contract {
             PromiseResult::Successful(_) => {}
             PromiseResult::Failed => {
                 // This reverts the changes from withdraw function.
-                // If account doesn't exit, deposits to the owner's account as lostfound.
-                let mut failed = false;
-                if let Some(mut account) = self.internal_get_account(&sender_id) {
-                    if account.deposit_with_storage_check(&token_id, amount.0) {
-                        // cause storage already checked, here can directly save
-                        self.accounts.insert(&sender_id, &account.into());
-                    } else {
-                        // we can ensure that internal_get_account here would NOT cause a version upgrade,
-                        // cause it is callback, the account must be the current version or non-exist,
-                        // so, here we can just leave it without insert, won't cause storage collection inconsistency.
-                        env::log(
-                            format!(
-                                "Account {} has not enough storage. Depositing to owner.",
-                                sender_id
-                            )
-                            .as_bytes(),
-                        );
-                        failed = true;
-                    }
-                } else {
-                    env::log(
-                        format!(
-                            "Account {} is not registered. Depositing to owner.",
-                            sender_id
-                        )
-                        .as_bytes(),
-                    );
-                    failed = true;
-                }
-                if failed {
-                    self.internal_lostfound(&token_id, amount.0);
+                Event::WithdrawFailed {
+                    account_id: &sender_id,
+                    token_id: &token_id,
+                    amount,
                 }
+                .emit();
+                self.internal_deposit_or_lostfound(&sender_id, &token_id, amount.0);
             }
         };
     }
@@ -272,34 +309,113 @@ impl Contract {
             ERR12_TOKEN_NOT_WHITELISTED
         );
         account.deposit(token_id, amount);
-        self.internal_save_account(&sender_id, account);
+        self.internal_save_account(sender_id, account);
+        Event::Deposit {
+            account_id: sender_id,
+            token_id,
+            amount: U128(amount),
+        }
+        .emit();
     }
 
-    // Return Option<Account> with accout_id
+    // Return Option<Account> with accout_id, upgraded to the current version
     pub fn internal_get_account(&self, account_id: &AccountId) -> Option<Account> {
-        self.accounts.get(account_id)
-    }
-    /// save token to owner account as lostfound, no need to care about storage
-    /// only global whitelisted token can be stored in lost-found
-    pub(crate) fn internal_lostfound(&mut self, token_id: &AccountId, amount: u128) {
-        if self.whitelisted_tokens.contains(token_id) {
-            let mut lostfound = self.internal_unwrap_or_default_account(&self.owner_id);
-            lostfound.deposit(token_id, amount);
-            self.accounts.insert(&self.owner_id, &lostfound.into());
+        self.accounts
+            .get(account_id)
+            .map(|account| account.into_current())
+            .or_else(|| {
+                self.legacy_accounts
+                    .get(account_id)
+                    .map(|account| account.into_current())
+            })
+    }
+
+    pub(crate) fn internal_is_registered(&self, account_id: &AccountId) -> bool {
+        self.accounts.contains_key(account_id) || self.legacy_accounts.contains_key(account_id)
+    }
+
+    /// Stores account in the current layout, moving it out of `legacy_accounts`.
+    /// Doesn't check its storage.
+    pub(crate) fn internal_put_account(&mut self, account_id: &AccountId, account: Account) {
+        self.accounts.insert(account_id, &account.into());
+        if self.legacy_accounts.contains_key(account_id) {
+            self.legacy_accounts.remove(account_id);
+        }
+    }
+
+    pub(crate) fn internal_remove_account(&mut self, account_id: &AccountId) {
+        self.accounts.remove(account_id);
+        self.legacy_accounts.remove(account_id);
+    }
+    /// Deposits tokens returned to given account from a callback.
+    /// If account doesn't exit or lacks storage, keeps whitelisted tokens as its lostfound.
+    /// Other tokens are deposited anyway, charging the storage to the account's storage deposit,
+    /// which it has to top up before its next change.
+    /// Returns whether the tokens were deposited.
+    pub(crate) fn internal_deposit_or_lostfound(
+        &mut self,
+        account_id: &AccountId,
+        token_id: &AccountId,
+        amount: Balance,
+    ) -> bool {
+        let mut failed = false;
+        if let Some(mut account) = self.internal_get_account(account_id) {
+            if account.deposit_with_storage_check(token_id, amount) {
+                // cause storage already checked, here can directly save
+                self.internal_put_account(account_id, account);
+            } else if !self.whitelisted_tokens.contains(token_id) {
+                account.deposit(token_id, amount);
+                // storage is left uncovered on purpose, so skip the check of internal_save_account
+                self.internal_put_account(account_id, account);
+            } else {
+                // upgrading the account on read doesn't write anything,
+                // so, here we can just leave it without insert, won't cause storage collection inconsistency.
+                failed = true;
+            }
         } else {
-            env::panic("ERR: non-whitelisted token can NOT deposit into lost-found.".as_bytes());
+            failed = true;
         }
+        if failed {
+            self.internal_lostfound(account_id, token_id, amount);
+        }
+        !failed
     }
 
-    pub fn internal_save_account(&mut self, account_id: &AccountId, account: Account) {
+    /// save token that failed to return to given account as its lostfound, storage is covered by the contract.
+    /// only global whitelisted token can be stored in lost-found,
+    /// `internal_send_tokens` makes sure others are only sent to registered accounts.
+    pub(crate) fn internal_lostfound(
+        &mut self,
+        account_id: &AccountId,
+        token_id: &AccountId,
+        amount: u128,
+    ) {
+        assert!(
+            self.whitelisted_tokens.contains(token_id),
+            "{}",
+            ERR141_LOSTFOUND_NOT_WHITELISTED
+        );
+        let key = (account_id.clone(), token_id.clone());
+        let lostfound = self.lostfound.get(&key).unwrap_or_default();
+        self.lostfound.insert(&key, &(lostfound + amount));
+        Event::Lostfound {
+            account_id,
+            token_id,
+            amount: U128(amount),
+        }
+        .emit();
+    }
+
+    pub fn internal_save_account(&mut self, account_id: &AccountId, mut account: Account) {
+        account.reconcile_storage_usage();
         account.assert_storage_usage();
-        self.accounts.insert(&account_id, &account.into());
+        self.internal_put_account(account_id, account);
     }
 
     pub(crate) fn internal_register_account(&mut self, account_id: &AccountId, amount: Balance) {
-        let mut account = self.internal_unwrap_or_default_account(&account_id);
+        let mut account = self.internal_unwrap_or_default_account(account_id);
         account.near_amount += amount;
-        self.internal_save_account(&account_id, account);
+        self.internal_save_account(account_id, account);
     }
 
     pub fn internal_unwrap_or_default_account(&self, account_id: &AccountId) -> Account {
@@ -312,7 +428,7 @@ impl Contract {
         account_id: &AccountId,
         amount: Balance,
     ) -> u128 {
-        let mut account = self.internal_unwrap_account(&account_id);
+        let mut account = self.internal_unwrap_account(account_id);
         let available = account.storage_available();
         assert!(available > 0, "ERR_NO_STORAGE_CAN_WITHDRAW");
         let mut withdraw_amount = amount;
@@ -324,7 +440,7 @@ impl Contract {
             "ERR_STORAGE_WITHDRAW_TOO_MUCH"
         );
         account.near_amount -= withdraw_amount;
-        self.internal_save_account(&account_id, account);
+        self.internal_save_account(account_id, account);
         withdraw_amount
     }
 
@@ -353,12 +469,19 @@ impl Contract {
 
     /// Sends given amount to given user and if it fails, returns it back to user's balance.
     /// Tokens must already be subtracted from internal balance.
+    /// Non-whitelisted tokens are only sent to registered users, whose storage deposit
+    /// covers them if the transfer fails, as they can't be kept in lost and found.
     pub(crate) fn internal_send_tokens(
         &self,
         sender_id: &AccountId,
         token_id: &AccountId,
         amount: Balance,
     ) -> Promise {
+        assert!(
+            self.whitelisted_tokens.contains(token_id) || self.internal_is_registered(sender_id),
+            "{}",
+            ERR141_LOSTFOUND_NOT_WHITELISTED
+        );
         ext_fungible_token::ft_transfer(
             sender_id.clone(),
             U128(amount),
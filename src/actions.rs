@@ -4,8 +4,10 @@ use near_sdk::{env, json_types::U128, AccountId, Balance};
 
 
 /// Single swap action
+/// Unknown fields are rejected, so a malformed swap by output is not taken for a swap.
 #[derive(Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
+#[serde(deny_unknown_fields)]
 pub struct SwapAction {
     /// Pool which should be used for swapping
     pub pool_id: u64,
@@ -25,12 +27,58 @@ pub struct SwapAction {
     pub min_amount_out: U128
 }
 
+/// Single swap action with exact amount of output.
+/// Unknown fields are rejected, so a malformed swap action is not taken for a swap by output.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(deny_unknown_fields)]
+pub struct SwapByOutputAction {
+    /// Pool which should be used for swapping
+    pub pool_id: u64,
+
+    /// Token swap from
+    pub token_in: AccountId,
+
+    /// Amount of token_out to receive.
+    pub amount_out: U128,
+
+    /// Token to swap into
+    pub token_out: AccountId,
+
+    /// Maximum amount of token_in allowed to spend
+    pub max_amount_in: U128,
+}
+
+/// Swap along the best route between two tokens, found by the contract.
+/// See `quote_best_route` for the route that will be taken.
+/// Unknown fields are rejected, so a malformed swap action is not taken for a route.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(deny_unknown_fields)]
+pub struct RouteAction {
+    /// Token swap from
+    pub token_in: AccountId,
+
+    /// amount to exchange.
+    /// if amount is None, it will take amount out from previous step.
+    pub amount_in: Option<U128>,
+
+    /// Token to swap into
+    pub token_out: AccountId,
+
+    /// Required minimum amount of token_out over the whole route
+    pub min_amount_out: U128,
+}
+
 /// Single action. Allows to execute sequence of various actions initiated by an account.
 #[derive(Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
 #[serde(untagged)]
 pub enum Action {
     Swap(SwapAction),
+    SwapByOutput(SwapByOutputAction),
+    /// Has to be the last one, as it would also match other swap actions.
+    Route(RouteAction),
 }
 
 impl Action {
@@ -40,6 +88,15 @@ impl Action {
             Action::Swap(swap_action) => {
                 vec![swap_action.token_in.clone(), swap_action.token_out.clone()]
             }
+            Action::SwapByOutput(swap_action) => {
+                vec![swap_action.token_in.clone(), swap_action.token_out.clone()]
+            }
+            Action::Route(route_action) => {
+                vec![
+                    route_action.token_in.clone(),
+                    route_action.token_out.clone(),
+                ]
+            }
         }
     }
 }
@@ -56,6 +113,7 @@ pub enum ActionResult {
 }
 
 impl ActionResult {
+    #[allow(clippy::wrong_self_convention)]
     pub fn to_amount(self) -> Balance {
         match self {
             // [AUDIT_02]
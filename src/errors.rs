@@ -24,9 +24,10 @@ pub const ERR29_ILLEGAL_WITHDRAW_AMOUNT: &str = "E29: Illegal withdraw amount";
 
 pub const ERR31_ZERO_AMOUNT: &str = "E31: adding zero amount";
 pub const ERR32_ZERO_SHARES: &str = "E32: minting zero shares";
-// // [AUDIT_07]
-// pub const ERR33_TRANSFER_TO_SELF: &str = "E33: transfer to self";
-// pub const ERR34_INSUFFICIENT_LP_SHARES: &str = "E34: insufficient lp shares";
+// [AUDIT_07]
+pub const ERR33_TRANSFER_TO_SELF: &str = "E33: transfer to self";
+pub const ERR34_INSUFFICIENT_LP_SHARES: &str = "E34: insufficient lp shares";
+pub const ERR35_ZAP_UNSUPPORTED_POOL: &str = "E35: only two token simple pools support adding liquidity with one token";
 
 // // Action result.
 
@@ -34,31 +35,91 @@ pub const ERR41_WRONG_ACTION_RESULT: &str = "E41: wrong action result type";
 
 // // Contract Level
 pub const ERR51_CONTRACT_PAUSED: &str = "E51: contract paused";
+pub const ERR52_CONTRACT_WITHDRAW_ONLY: &str = "E52: contract only allows withdrawals";
 
-// // Swap
-// pub const ERR60_DECIMAL_ILLEGAL: &str = "E60: illegal decimal";
-// pub const ERR61_AMP_ILLEGAL: &str = "E61: illegal amp";
-// pub const ERR62_FEE_ILLEGAL: &str = "E62: illegal fee";
-// pub const ERR63_MISSING_TOKEN: &str = "E63: missing token";
-// pub const ERR64_TOKENS_COUNT_ILLEGAL: &str = "E64: illegal tokens count";
-// pub const ERR65_INIT_TOKEN_BALANCE: &str = "E65: init token balance should be non-zero";
-// pub const ERR66_INVARIANT_CALC_ERR: &str = "E66: encounter err when calc invariant D";
-// pub const ERR67_LPSHARE_CALC_ERR: &str = "E67: encounter err when calc lp shares";
-// pub const ERR68_SLIPPAGE: &str = "E68: slippage error";
-// pub const ERR69_MIN_RESERVE: &str = "E69: pool reserved token balance less than MIN_RESERVE";
-// pub const ERR70_SWAP_OUT_CALC_ERR: &str = "E70: encounter err when calc swap out";
-// pub const ERR71_SWAP_DUP_TOKENS: &str = "E71: illegal swap with duplicated tokens";
-
-// // pool manage
-// pub const ERR81_AMP_IN_LOCK: &str = "E81: amp is currently in lock";
-// pub const ERR82_INSUFFICIENT_RAMP_TIME: &str = "E82: insufficient ramp time";
-// pub const ERR83_INVALID_AMP_FACTOR: &str = "E83: invalid amp factor";
-// pub const ERR84_AMP_LARGE_CHANGE: &str = "E84: amp factor change is too large";
-pub const ERR_DUPLICATE_TOKENS: &str = "Error: 2 tokens should be different from each other";
+// Swap
+pub const ERR60_DECIMAL_ILLEGAL: &str = "E60: illegal decimal";
+pub const ERR61_AMP_ILLEGAL: &str = "E61: illegal amp";
+pub const ERR62_FEE_ILLEGAL: &str = "E62: illegal fee";
+pub const ERR63_MISSING_TOKEN: &str = "E63: missing token";
+pub const ERR64_TOKENS_COUNT_ILLEGAL: &str = "E64: illegal tokens count";
+pub const ERR65_INIT_TOKEN_BALANCE: &str = "E65: init token balance should be non-zero";
+pub const ERR66_INVARIANT_CALC_ERR: &str = "E66: encounter err when calc invariant D";
+pub const ERR67_LPSHARE_CALC_ERR: &str = "E67: encounter err when calc lp shares";
+pub const ERR68_SLIPPAGE: &str = "E68: slippage error";
+pub const ERR69_MIN_RESERVE: &str = "E69: pool reserved token balance less than MIN_RESERVE";
+pub const ERR70_SWAP_OUT_CALC_ERR: &str = "E70: encounter err when calc swap out";
+pub const ERR71_SWAP_DUP_TOKENS: &str = "E71: illegal swap with duplicated tokens";
+pub const ERR72_WEIGHT_ILLEGAL: &str = "E72: illegal token weights";
+pub const ERR73_MAX_RATIO: &str = "E73: swap amount exceeds max ratio of pool balance";
+pub const ERR74_POOL_NOT_MIGRATED: &str = "E74: pool is not migrated yet";
+pub const ERR75_POOL_NOT_RUNNING: &str = "E75: pool is not running";
+pub const ERR76_POOL_LIQUIDITY_CLOSED: &str = "E76: pool does not accept liquidity";
+pub const ERR77_AMOUNT_OVERFLOW: &str = "E77: amount overflows normalized precision";
+pub const ERR78_POOL_DEPRECATED: &str = "E78: pool is deprecated";
+
+// pool manage
+pub const ERR81_AMP_IN_LOCK: &str = "E81: amp is currently in lock";
+pub const ERR82_INSUFFICIENT_RAMP_TIME: &str = "E82: insufficient ramp time";
+pub const ERR83_INVALID_AMP_FACTOR: &str = "E83: invalid amp factor";
+pub const ERR84_AMP_LARGE_CHANGE: &str = "E84: amp factor change is too large";
+pub const ERR_DUPLICATE_TOKENS: &str = "Error: tokens should be different from each other";
 pub const ERR85_NO_POOL: &str = "E85: invalid pool id";
+pub const ERR86_UNSUPPORTED_POOL_KIND: &str = "E86: operation not supported by this pool kind";
+pub const ERR87_POOL_EXISTED: &str = "E87: pool with same tokens and fee already exists";
+pub const ERR88_FEE_TIER_NOT_ALLOWED: &str = "E88: fee is not an allowed fee tier";
+pub const ERR89_FEE_TIER_EXISTED: &str = "E89: fee tier already exists";
+
+// oracle
+pub const ERR90_TWAP_WINDOW_ILLEGAL: &str = "E90: illegal twap window";
+pub const ERR91_TWAP_INSUFFICIENT_HISTORY: &str = "E91: not enough price history for twap window";
+
+// flash loan
+pub const ERR92_NOT_FLASH_LOAN_BORROWER: &str = "E92: account is not allowed to take flash loans";
+pub const ERR93_NO_FLASH_LOAN: &str = "E93: no outstanding flash loan in this pool";
+pub const ERR94_FLASH_LOAN_IN_PROGRESS: &str = "E94: pool is locked by outstanding flash loan";
+pub const ERR95_FLASH_LOAN_WRONG_TOKEN: &str = "E95: repaid token differs from borrowed one";
+pub const ERR96_FLASH_LOAN_AMOUNT: &str = "E96: flash loan amount exceeds pool reserve";
+
+// limit order
+pub const ERR110_NO_LIMIT_ORDER: &str = "E110: limit order not found";
+pub const ERR111_NOT_ORDER_OWNER: &str = "E111: limit order belongs to another account";
+pub const ERR112_ORDER_NOT_FILLED: &str = "E112: limit order is not filled yet";
+pub const ERR113_ORDER_ALREADY_FILLED: &str = "E113: limit order is already filled";
+pub const ERR114_TOO_MANY_ORDERS: &str = "E114: too many open limit orders in this pool";
+pub const ERR115_TOO_MANY_ACCOUNT_ORDERS: &str = "E115: too many limit orders of this account";
+pub const ERR116_INSUFFICIENT_ORDER_BOND: &str = "E116: not enough storage balance for order bond";
+
+// farming
+pub const ERR120_NO_FARM: &str = "E120: farm not found";
+pub const ERR121_FARM_TIME_ILLEGAL: &str = "E121: illegal farm start or end time";
+pub const ERR122_FARM_NOT_ENDED: &str = "E122: farm has not ended yet";
+pub const ERR123_FARM_REWARD_OVERFLOW: &str = "E123: farm total reward overflow";
+
+// router
+pub const ERR130_NO_ROUTE: &str = "E130: no route found between tokens";
+
+// lost and found
+pub const ERR140_NO_LOSTFOUND: &str = "E140: no lost and found tokens to claim";
+pub const ERR141_LOSTFOUND_NOT_WHITELISTED: &str =
+    "E141: non-whitelisted token can't be kept as lost and found";
+
+// wnear
+pub const ERR150_WNEAR_NOT_SET: &str = "E150: wnear contract is not set";
+
+// timelock
+pub const ERR160_NO_PROPOSAL: &str = "E160: admin proposal not found";
+pub const ERR161_PROPOSAL_NOT_PENDING: &str = "E161: admin proposal is not pending";
+pub const ERR162_PROPOSAL_TIMELOCKED: &str = "E162: admin proposal is still timelocked";
+pub const ERR163_ILLEGAL_TIMELOCK_DELAY: &str = "E163: illegal timelock delay";
+pub const ERR164_PROPOSAL_NOT_VETOABLE: &str = "E164: admin proposal can not be vetoed";
+
+// upgrade
+pub const ERR170_NO_STAGED_CODE: &str = "E170: no staged code";
+pub const ERR171_STAGED_CODE_TIMELOCKED: &str = "E171: staged code can not be deployed yet";
 
 // owner
 pub const ERR100_NOT_ALLOWED: &str = "E100: no permission to invoke this";
-// pub const ERR101_ILLEGAL_FEE: &str = "E101: illegal fee";
-// pub const ERR102_INVALID_TOKEN_ID: &str = "E102: invalid token id";
+pub const ERR101_ILLEGAL_FEE: &str = "E101: illegal fee";
+pub const ERR102_INVALID_TOKEN_ID: &str = "E102: invalid token id";
 pub const ERR103_NOT_INITIALIZED: &str = "E103: contract is not initialized";
@@ -0,0 +1,154 @@
+//! Events in NEP-297 format, logged as `EVENT_JSON:{"standard":..,"version":..,"event":..,"data":..}`.
+//! Schema of each event's data is bound to `EVENT_STANDARD_VERSION`, which has to be bumped on any change.
+
+use near_sdk::json_types::{Base58CryptoHash, U128};
+use near_sdk::serde::Serialize;
+use near_sdk::serde_json;
+use near_sdk::{env, AccountId};
+
+use crate::timelock::{AdminAction, ProposalStatus};
+
+pub const EVENT_STANDARD: &str = "forbitswap";
+pub const EVENT_STANDARD_VERSION: &str = "1.4.0";
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum Event<'a> {
+    Swap {
+        account_id: &'a AccountId,
+        pool_id: u64,
+        token_in: &'a AccountId,
+        amount_in: U128,
+        token_out: &'a AccountId,
+        amount_out: U128,
+    },
+    AddLiquidity {
+        account_id: &'a AccountId,
+        pool_id: u64,
+        /// Amounts of pool tokens taken, in order of the pool tokens.
+        amounts: Vec<U128>,
+        shares: U128,
+    },
+    RemoveLiquidity {
+        account_id: &'a AccountId,
+        pool_id: u64,
+        shares: U128,
+        /// Amounts of pool tokens returned, in order of the pool tokens.
+        amounts: Vec<U128>,
+    },
+    Deposit {
+        account_id: &'a AccountId,
+        token_id: &'a AccountId,
+        amount: U128,
+    },
+    Withdraw {
+        account_id: &'a AccountId,
+        token_id: &'a AccountId,
+        amount: U128,
+    },
+    /// Transfer of an earlier `Withdraw` failed, the tokens are returned to the deposits of `account_id`,
+    /// or kept as lost and found if followed by `Lostfound`.
+    WithdrawFailed {
+        account_id: &'a AccountId,
+        token_id: &'a AccountId,
+        amount: U128,
+    },
+    /// Transfer of LP shares, refunds of shares unused by the receiver of `mft_transfer_call`
+    /// are transfers back.
+    ShareTransfer {
+        pool_id: u64,
+        sender_id: &'a AccountId,
+        receiver_id: &'a AccountId,
+        amount: U128,
+        memo: Option<&'a str>,
+    },
+    /// Settlement of a flash loan: `used` of lent `amount` with its fee is `required` back,
+    /// `repaid` by the receiver and `charged` from the borrower's deposits for a shortfall.
+    FlashLoan {
+        pool_id: u64,
+        borrower_id: &'a AccountId,
+        token_id: &'a AccountId,
+        amount: U128,
+        used: U128,
+        required: U128,
+        repaid: U128,
+        charged: U128,
+    },
+    PoolCreated {
+        pool_id: u64,
+        pool_kind: String,
+        tokens: &'a [AccountId],
+        fee: u32,
+    },
+    FeeChanged {
+        exchange_fee: u32,
+        referral_fee: u32,
+    },
+    StateChanged {
+        from: String,
+        to: String,
+        changed_by: &'a AccountId,
+    },
+    PoolStateChanged {
+        pool_id: u64,
+        from: String,
+        to: String,
+        changed_by: &'a AccountId,
+    },
+    /// Tokens that could not be returned to `account_id` and were kept as lost and found.
+    Lostfound {
+        account_id: &'a AccountId,
+        token_id: &'a AccountId,
+        amount: U128,
+    },
+    AdminProposalCreated {
+        proposal_id: u64,
+        action: &'a AdminAction,
+        /// Time (in seconds) from which the proposal can be executed.
+        executable_at: u64,
+    },
+    AdminProposalClosed {
+        proposal_id: u64,
+        status: ProposalStatus,
+        closed_by: &'a AccountId,
+    },
+    UnmanagedTokenRetrieved {
+        token_id: &'a AccountId,
+        amount: U128,
+        receiver_id: &'a AccountId,
+    },
+    /// Pools from `from_index` up to `to_index` were converted to the current version.
+    PoolsMigrated { from_index: u64, to_index: u64 },
+    CodeStaged {
+        code_hash: Base58CryptoHash,
+        /// Time (in seconds) from which the code can be deployed.
+        deployable_at: u64,
+    },
+    StagedCodeRemoved {
+        code_hash: Base58CryptoHash,
+        removed_by: &'a AccountId,
+    },
+    /// Code was deployed and migrated, logged by the deployed code.
+    CodeDeployed { code_hash: Base58CryptoHash },
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct EventLog<'a> {
+    standard: &'static str,
+    version: &'static str,
+    #[serde(flatten)]
+    event: &'a Event<'a>,
+}
+
+impl Event<'_> {
+    pub fn emit(&self) {
+        let log = EventLog {
+            standard: EVENT_STANDARD,
+            version: EVENT_STANDARD_VERSION,
+            event: self,
+        };
+        env::log(format!("EVENT_JSON:{}", serde_json::to_string(&log).unwrap()).as_bytes());
+    }
+}
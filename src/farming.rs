@@ -0,0 +1,259 @@
+//! Liquidity mining programs rewarding LPs of a pool.
+//!
+//! Owner funds a farm of a pool with `reward_per_second * (end_at - start_at)` of reward token
+//! from its deposits. Rewards are shared between LPs pro-rata to their shares in the pool.
+//! Admin fee shares held by the exchange account itself don't earn rewards, as nobody could claim them.
+//! Every change of share balances has to checkpoint affected accounts first with
+//! `internal_checkpoint_farmers`, so rewards up to that moment are accrued with the old balances.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::utils::{now_sec, U256};
+use crate::*;
+
+/// Precision of accumulated reward per share.
+const REWARD_PER_SHARE_PRECISION: u128 = 1_000_000_000_000_000_000_000_000;
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct Farm {
+    pub pool_id: u64,
+    pub reward_token: AccountId,
+    pub reward_per_second: Balance,
+    /// Reward period in seconds.
+    pub start_at: u64,
+    pub end_at: u64,
+    /// Time (in seconds) rewards were accumulated up to.
+    pub last_update: u64,
+    /// Rewards per share accumulated so far, in `REWARD_PER_SHARE_PRECISION`.
+    pub acc_reward_per_share: U256,
+    /// Amount funded, decreased by the leftover returned to owner.
+    pub total_reward: Balance,
+    /// Amount accumulated for LPs so far.
+    pub distributed: Balance,
+}
+
+impl Farm {
+    /// Accumulates rewards since last update up to `now` for `total_shares` of the pool.
+    /// Rewards of periods without any shares are left undistributed.
+    fn update(&mut self, total_shares: Balance, now: u64) {
+        let from = std::cmp::max(self.last_update, self.start_at);
+        let to = std::cmp::min(now, self.end_at);
+        if to > from && total_shares > 0 {
+            let reward = self.reward_per_second * (to - from) as Balance;
+            self.acc_reward_per_share += U256::from(reward)
+                * U256::from(REWARD_PER_SHARE_PRECISION)
+                / U256::from(total_shares);
+            self.distributed += reward;
+        }
+        self.last_update = std::cmp::max(self.last_update, now);
+    }
+}
+
+/// Rewards state of an LP in a farm.
+#[derive(BorshSerialize, BorshDeserialize, Default)]
+pub struct Farmer {
+    /// Farm's `acc_reward_per_share` as of the last checkpoint.
+    pub acc_reward_per_share: U256,
+    /// Rewards accrued up to the last checkpoint.
+    pub unclaimed: Balance,
+}
+
+impl Farmer {
+    fn checkpoint(&mut self, farm: &Farm, shares: Balance) {
+        self.unclaimed += ((farm.acc_reward_per_share - self.acc_reward_per_share)
+            * U256::from(shares)
+            / U256::from(REWARD_PER_SHARE_PRECISION))
+        .as_u128();
+        self.acc_reward_per_share = farm.acc_reward_per_share;
+    }
+}
+
+/// Shares of given account in the pool that earn farm rewards.
+fn farmed_shares(pool: &Pool, account_id: &AccountId) -> Balance {
+    if account_id == &env::current_account_id() {
+        0
+    } else {
+        pool.share_balances(account_id)
+    }
+}
+
+/// Shares of the pool that earn farm rewards, all but the exchange's own.
+fn farmed_total_shares(pool: &Pool) -> Balance {
+    pool.share_total_balance() - pool.share_balances(&env::current_account_id())
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct FarmInfo {
+    pub farm_id: u64,
+    pub pool_id: u64,
+    pub reward_token: AccountId,
+    pub reward_per_second: U128,
+    pub start_at: u64,
+    pub end_at: u64,
+    pub total_reward: U128,
+    pub distributed: U128,
+}
+
+impl FarmInfo {
+    fn new(farm_id: u64, farm: Farm) -> Self {
+        Self {
+            farm_id,
+            pool_id: farm.pool_id,
+            reward_token: farm.reward_token,
+            reward_per_second: U128(farm.reward_per_second),
+            start_at: farm.start_at,
+            end_at: farm.end_at,
+            total_reward: U128(farm.total_reward),
+            distributed: U128(farm.distributed),
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Creates farm of given pool, paying `reward_per_second` of `reward_token` between `start_at` and `end_at`
+    /// (in seconds). Rewards are taken from owner's deposits. Only can be called by owner.
+    /// Attached deposit should cover the storage, the rest is refunded. Returns id of the farm.
+    #[payable]
+    pub fn create_farm(
+        &mut self,
+        pool_id: u64,
+        reward_token: ValidAccountId,
+        reward_per_second: U128,
+        start_at: u64,
+        end_at: u64,
+    ) -> u64 {
+        self.assert_owner();
+        assert!(
+            start_at >= now_sec() && end_at > start_at,
+            "{}",
+            ERR121_FARM_TIME_ILLEGAL
+        );
+        assert!(reward_per_second.0 > 0, "{}", ERR31_ZERO_AMOUNT);
+        let prev_storage = env::storage_usage();
+        assert!(pool_id < self.pools.len(), "{}", ERR85_NO_POOL);
+        let total_reward = reward_per_second
+            .0
+            .checked_mul((end_at - start_at) as Balance)
+            .expect(ERR123_FARM_REWARD_OVERFLOW);
+        let mut account = self.internal_unwrap_account(&self.owner_id);
+        account.withdraw(reward_token.as_ref(), total_reward);
+        self.internal_save_account(&self.owner_id.clone(), account);
+
+        let farm_id = self.farms.len();
+        self.farms.push(&Farm {
+            pool_id,
+            reward_token: reward_token.into(),
+            reward_per_second: reward_per_second.0,
+            start_at,
+            end_at,
+            last_update: start_at,
+            acc_reward_per_share: U256::zero(),
+            total_reward,
+            distributed: 0,
+        });
+        let mut farm_ids = self.pool_farms.get(&pool_id).unwrap_or_default();
+        farm_ids.push(farm_id);
+        self.pool_farms.insert(&pool_id, &farm_ids);
+        self.internal_check_storage(prev_storage);
+        farm_id
+    }
+
+    /// Returns rewards that were not distributed because the pool had no farmed shares to owner's deposits.
+    /// Only can be called by owner, once the farm has ended.
+    pub fn withdraw_farm_leftover(&mut self, farm_id: u64) -> U128 {
+        self.assert_owner();
+        let mut farm = self.farms.get(farm_id).expect(ERR120_NO_FARM);
+        assert!(now_sec() >= farm.end_at, "{}", ERR122_FARM_NOT_ENDED);
+        let pool = self.internal_get_pool(farm.pool_id).expect(ERR85_NO_POOL);
+        farm.update(farmed_total_shares(&pool), now_sec());
+        let leftover = farm.total_reward - farm.distributed;
+        farm.total_reward = farm.distributed;
+        self.farms.replace(farm_id, &farm);
+        if leftover > 0 {
+            let mut account = self.internal_unwrap_account(&self.owner_id);
+            account.deposit(&farm.reward_token, leftover);
+            self.internal_save_account(&self.owner_id.clone(), account);
+        }
+        U128(leftover)
+    }
+
+    /// Claims rewards of the predecessor in given farm into its deposits.
+    /// Returns claimed amount.
+    pub fn claim_farm_rewards(&mut self, farm_id: u64) -> U128 {
+        let sender_id = env::predecessor_account_id();
+        let farm = self.farms.get(farm_id).expect(ERR120_NO_FARM);
+        let pool = self.internal_get_pool(farm.pool_id).expect(ERR85_NO_POOL);
+        self.internal_checkpoint_farmers(farm.pool_id, &pool, &[&sender_id]);
+        let key = (farm_id, sender_id.clone());
+        let mut farmer = self.farmers.get(&key).unwrap_or_default();
+        let amount = farmer.unclaimed;
+        if amount > 0 {
+            farmer.unclaimed = 0;
+            self.farmers.insert(&key, &farmer);
+            let mut account = self.internal_unwrap_account(&sender_id);
+            account.deposit(&farm.reward_token, amount);
+            self.internal_save_account(&sender_id, account);
+        }
+        U128(amount)
+    }
+
+    pub fn get_farm(&self, farm_id: u64) -> Option<FarmInfo> {
+        self.farms
+            .get(farm_id)
+            .map(|farm| FarmInfo::new(farm_id, farm))
+    }
+
+    pub fn get_pool_farms(&self, pool_id: u64) -> Vec<FarmInfo> {
+        self.pool_farms
+            .get(&pool_id)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|farm_id| FarmInfo::new(farm_id, self.farms.get(farm_id).unwrap()))
+            .collect()
+    }
+
+    /// Returns rewards given account can claim from given farm right now.
+    pub fn get_unclaimed_farm_rewards(&self, farm_id: u64, account_id: ValidAccountId) -> U128 {
+        let mut farm = self.farms.get(farm_id).expect(ERR120_NO_FARM);
+        let pool = self.internal_get_pool(farm.pool_id).expect(ERR85_NO_POOL);
+        farm.update(farmed_total_shares(&pool), now_sec());
+        let mut farmer = self
+            .farmers
+            .get(&(farm_id, account_id.to_string()))
+            .unwrap_or_default();
+        farmer.checkpoint(&farm, farmed_shares(&pool, account_id.as_ref()));
+        U128(farmer.unclaimed)
+    }
+}
+
+impl Contract {
+    /// Accrues rewards of all farms of given pool for given accounts, using current share balances.
+    /// Must be called before share balances of these accounts or total supply of the pool change.
+    pub(crate) fn internal_checkpoint_farmers(
+        &mut self,
+        pool_id: u64,
+        pool: &Pool,
+        account_ids: &[&AccountId],
+    ) {
+        let farm_ids = match self.pool_farms.get(&pool_id) {
+            Some(farm_ids) => farm_ids,
+            None => return,
+        };
+        let now = now_sec();
+        for farm_id in farm_ids {
+            let mut farm = self.farms.get(farm_id).unwrap();
+            farm.update(farmed_total_shares(pool), now);
+            for account_id in account_ids {
+                let key = (farm_id, (*account_id).clone());
+                let mut farmer = self.farmers.get(&key).unwrap_or_default();
+                farmer.checkpoint(&farm, farmed_shares(pool, account_id));
+                self.farmers.insert(&key, &farmer);
+            }
+            self.farms.replace(farm_id, &farm);
+        }
+    }
+}
@@ -0,0 +1,206 @@
+//! Flash loans out of pool reserves.
+//!
+//! Borrowed tokens are sent to the receiver with `ft_transfer_call`. Receiver has to repay
+//! `used amount + fee` before its `ft_on_transfer` resolves, by transferring tokens back to this
+//! contract with `{"repay_flash_loan": <pool_id>}` message. The pool is locked until the loan resolves.
+//! Borrowers don't need any deposits: if the receiver fails, the token contract refunds the loan.
+//! As there is no way to revert the transfer otherwise, only allowed borrowers can take loans.
+//! A loan repaid short is rolled back as far as possible: the shortfall is charged from
+//! the borrower's deposits, and a borrower who can't cover it loses the right to borrow.
+
+use near_contract_standards::fungible_token::core_impl::ext_fungible_token;
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+
+use crate::utils::{
+    ext_self, FEE_DIVISOR, GAS_FOR_FT_TRANSFER_CALL, GAS_FOR_RESOLVE_TRANSFER, U256,
+};
+use crate::*;
+
+/// Outstanding flash loan of a pool.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct FlashLoan {
+    pub borrower_id: AccountId,
+    pub token_id: AccountId,
+    pub amount: Balance,
+    /// Amount transferred back while the loan is outstanding.
+    pub repaid: Balance,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Lends `amount` of `token_id` from given pool to `receiver_id` via `ft_transfer_call` with `msg`.
+    /// Only can be called by allowed borrowers. Resolves to amount of token put back into the pool.
+    #[payable]
+    pub fn flash_loan(
+        &mut self,
+        pool_id: u64,
+        token_id: ValidAccountId,
+        amount: U128,
+        receiver_id: ValidAccountId,
+        msg: String,
+    ) -> Promise {
+        assert_one_yocto();
+        self.assert_contract_running();
+        let borrower_id = env::predecessor_account_id();
+        assert!(
+            self.flash_loan_borrowers.contains(&borrower_id),
+            "{}",
+            ERR92_NOT_FLASH_LOAN_BORROWER
+        );
+        self.assert_no_flash_loan(pool_id);
+        self.assert_pool_running(pool_id);
+        assert!(amount.0 > 0, "{}", ERR31_ZERO_AMOUNT);
+
+        let mut pool = self.internal_get_pool(pool_id).expect(ERR85_NO_POOL);
+        pool.lend(token_id.as_ref(), amount.0);
+        self.internal_save_pool(pool_id, pool);
+        self.flash_loans.insert(
+            &pool_id,
+            &FlashLoan {
+                borrower_id,
+                token_id: token_id.clone().into(),
+                amount: amount.0,
+                repaid: 0,
+            },
+        );
+
+        ext_fungible_token::ft_transfer_call(
+            receiver_id.into(),
+            amount,
+            None,
+            msg,
+            token_id.as_ref(),
+            1,
+            env::prepaid_gas() - GAS_FOR_FT_TRANSFER_CALL,
+        )
+        .then(ext_self::callback_flash_loan(
+            pool_id,
+            &env::current_account_id(),
+            0,
+            GAS_FOR_RESOLVE_TRANSFER,
+        ))
+    }
+
+    /// Resolves flash loan of given pool: puts back used amount with its fee out of the repaid tokens
+    /// and returns the rest to the borrower. Shortfall is charged from the borrower's deposits.
+    #[private]
+    pub fn callback_flash_loan(&mut self, pool_id: u64) -> U128 {
+        let loan = self
+            .flash_loans
+            .remove(&pool_id)
+            .expect(ERR93_NO_FLASH_LOAN);
+        // Token contract refunds to this contract whatever the receiver didn't use.
+        let used = match env::promise_result(0) {
+            PromiseResult::NotReady => unreachable!(),
+            PromiseResult::Successful(value) => near_sdk::serde_json::from_slice::<U128>(&value)
+                .map(|used| std::cmp::min(loan.amount, used.0))
+                .unwrap_or(loan.amount),
+            PromiseResult::Failed => 0,
+        };
+        let mut pool = self.internal_get_pool(pool_id).expect(ERR85_NO_POOL);
+        let required = used + flash_loan_fee(used, pool.get_fee());
+        let charged = if loan.repaid >= required {
+            let refund = loan.repaid - required;
+            if refund > 0 {
+                self.internal_deposit_or_lostfound(&loan.borrower_id, &loan.token_id, refund);
+            }
+            0
+        } else {
+            self.internal_charge_flash_loan_shortfall(&loan, required - loan.repaid)
+        };
+        Event::FlashLoan {
+            pool_id,
+            borrower_id: &loan.borrower_id,
+            token_id: &loan.token_id,
+            amount: U128(loan.amount),
+            used: U128(used),
+            required: U128(required),
+            repaid: U128(loan.repaid),
+            charged: U128(charged),
+        }
+        .emit();
+        let returned = loan.amount - used + std::cmp::min(loan.repaid, required) + charged;
+        pool.repay(&loan.token_id, returned);
+        self.internal_save_pool(pool_id, pool);
+        U128(returned)
+    }
+
+    /// Allow given accounts to take flash loans. Only can be called by owner.
+    #[payable]
+    pub fn extend_flash_loan_borrowers(&mut self, borrowers: Vec<ValidAccountId>) {
+        assert_one_yocto();
+        self.assert_owner();
+        for borrower in borrowers {
+            self.flash_loan_borrowers.insert(borrower.as_ref());
+        }
+    }
+
+    /// Disallow given accounts to take flash loans. Only can be called by pause managers.
+    #[payable]
+    pub fn remove_flash_loan_borrowers(&mut self, borrowers: Vec<ValidAccountId>) {
+        assert_one_yocto();
+        self.assert_role(Role::PauseManager);
+        for borrower in borrowers {
+            self.flash_loan_borrowers.remove(borrower.as_ref());
+        }
+    }
+
+    pub fn get_flash_loan_borrowers(&self) -> Vec<AccountId> {
+        self.flash_loan_borrowers.to_vec()
+    }
+}
+
+/// Returns fee of flash loan of given amount, rounded up in favour of the pool.
+fn flash_loan_fee(amount: Balance, fee: u32) -> Balance {
+    ((U256::from(amount) * U256::from(fee) + U256::from(FEE_DIVISOR - 1)) / U256::from(FEE_DIVISOR))
+        .as_u128()
+}
+
+impl Contract {
+    /// Panics if given pool has outstanding flash loan, so its reserves can't be traded or withdrawn.
+    pub(crate) fn assert_no_flash_loan(&self, pool_id: u64) {
+        assert!(
+            !self.flash_loans.contains_key(&pool_id),
+            "{}",
+            ERR94_FLASH_LOAN_IN_PROGRESS
+        );
+    }
+
+    /// Records tokens transferred back to repay outstanding flash loan of given pool.
+    pub(crate) fn internal_repay_flash_loan(
+        &mut self,
+        pool_id: u64,
+        token_id: &AccountId,
+        amount: Balance,
+    ) {
+        let mut loan = self.flash_loans.get(&pool_id).expect(ERR93_NO_FLASH_LOAN);
+        assert_eq!(&loan.token_id, token_id, "{}", ERR95_FLASH_LOAN_WRONG_TOKEN);
+        loan.repaid += amount;
+        self.flash_loans.insert(&pool_id, &loan);
+    }
+
+    /// Charges up to `shortfall` of the loaned token from the borrower's deposits, returns amount charged.
+    /// Borrower not covering the whole shortfall isn't allowed to take loans anymore.
+    fn internal_charge_flash_loan_shortfall(
+        &mut self,
+        loan: &FlashLoan,
+        shortfall: Balance,
+    ) -> Balance {
+        let charged = match self.internal_get_account(&loan.borrower_id) {
+            Some(mut account) => {
+                let charged =
+                    std::cmp::min(account.get_balance(&loan.token_id).unwrap_or(0), shortfall);
+                if charged > 0 {
+                    account.withdraw(&loan.token_id, charged);
+                    self.internal_save_account(&loan.borrower_id, account);
+                }
+                charged
+            }
+            None => 0,
+        };
+        if charged < shortfall {
+            self.flash_loan_borrowers.remove(&loan.borrower_id);
+        }
+        charged
+    }
+}
@@ -0,0 +1,120 @@
+//! Layouts of previous versions, read by migrations.
+//!
+//! The first version stored the contract, accounts without the `VAccount` tag and
+//! pools of a single kind, all in the layouts below.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::{LazyOption, LookupMap, UnorderedMap, UnorderedSet, Vector};
+
+use crate::account::{Account, INIT_ACCOUNT_STORAGE};
+use crate::migration::PoolMigration;
+use crate::pool::VPool;
+use crate::simple_pool::SimplePool;
+use crate::timelock::DEFAULT_TIMELOCK_DELAY_SEC;
+use crate::utils::SwapVolume;
+use crate::*;
+
+/// Account of the first version, stored without the `VAccount` tag.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct AccountV1 {
+    pub near_amount: Balance,
+    pub tokens: UnorderedMap<AccountId, Balance>,
+    /// Unused, the record was always charged as `INIT_ACCOUNT_STORAGE`.
+    pub storage_used: StorageUsage,
+}
+
+impl AccountV1 {
+    pub fn into_current(self) -> Account {
+        Account {
+            near_amount: self.near_amount,
+            tokens: self.tokens,
+            storage_used: INIT_ACCOUNT_STORAGE,
+        }
+    }
+}
+
+/// Simple pool of the first version, without price oracle and with fields obsolete since
+/// admin fees moved to the contract.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct SimplePoolV1 {
+    pub token_account_ids: Vec<AccountId>,
+    pub amounts: Vec<Balance>,
+    pub volumes: Vec<SwapVolume>,
+    pub total_fee: u32,
+    pub exchange_fee: u32,
+    pub referral_fee: u32,
+    pub shares: UnorderedMap<AccountId, Balance>,
+    pub shares_total_supply: Balance,
+    pub first_provider: Option<AccountId>,
+}
+
+impl SimplePoolV1 {
+    /// Converts pool for reading, without the price oracle.
+    pub fn into_current(self) -> SimplePool {
+        SimplePool {
+            token_account_ids: self.token_account_ids,
+            amounts: self.amounts,
+            volumes: self.volumes,
+            total_fee: self.total_fee,
+            shares: self.shares,
+            shares_total_supply: self.shares_total_supply,
+            oracle: None,
+        }
+    }
+}
+
+/// Contract of the first version.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct ContractV1 {
+    owner_id: AccountId,
+    accounts: LookupMap<AccountId, AccountV1>,
+    pools: Vector<VPool>,
+    exchange_fee: u32,
+    referral_fee: u32,
+    whitelisted_tokens: UnorderedSet<AccountId>,
+    guardians: UnorderedSet<AccountId>,
+    state: RunningState,
+}
+
+impl ContractV1 {
+    /// Sets up everything added since, pools stored so far are left to `migrate_pools`.
+    pub fn into_current(self) -> Contract {
+        Contract {
+            pool_migration: PoolMigration {
+                next_index: 0,
+                end_index: self.pools.len(),
+            },
+            owner_id: self.owner_id,
+            accounts: LookupMap::new(StorageKey::VAccounts),
+            legacy_accounts: self.accounts,
+            pools: self.pools,
+            exchange_fee: self.exchange_fee,
+            referral_fee: self.referral_fee,
+            whitelisted_tokens: self.whitelisted_tokens,
+            guardians: self.guardians,
+            state: self.state,
+            flash_loans: LookupMap::new(StorageKey::FlashLoans),
+            flash_loan_borrowers: UnorderedSet::new(StorageKey::FlashLoanBorrowers),
+            limit_orders: LookupMap::new(StorageKey::LimitOrders),
+            pool_limit_orders: LookupMap::new(StorageKey::PoolLimitOrders),
+            next_limit_order_id: 0,
+            limit_order_counts: LookupMap::new(StorageKey::LimitOrderCounts),
+            farms: Vector::new(StorageKey::Farms),
+            pool_farms: LookupMap::new(StorageKey::PoolFarms),
+            farmers: LookupMap::new(StorageKey::Farmers),
+            pair_pools: LookupMap::new(StorageKey::PairPools),
+            token_pools: LookupMap::new(StorageKey::TokenPools),
+            pool_keys: LookupMap::new(StorageKey::PoolKeys),
+            fee_tiers: DEFAULT_FEE_TIERS.to_vec(),
+            lostfound: LookupMap::new(StorageKey::Lostfound),
+            wnear_id: None,
+            admin_proposals: Vector::new(StorageKey::AdminProposals),
+            timelock_delay_sec: DEFAULT_TIMELOCK_DELAY_SEC,
+            roles: UnorderedMap::new(StorageKey::Roles),
+            staged_code: LazyOption::new(StorageKey::StagedCode, None),
+            staged_upgrade: None,
+            upgrade_delay_sec: DEFAULT_TIMELOCK_DELAY_SEC,
+            pool_states: LookupMap::new(StorageKey::PoolStates),
+        }
+    }
+}
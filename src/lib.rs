@@ -1,35 +1,65 @@
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fmt;
 
 use actions::{ActionResult, SwapAction};
 use admin_fee::AdminFees;
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::{LookupMap, UnorderedSet, Vector};
+use near_sdk::collections::{LazyOption, LookupMap, UnorderedMap, UnorderedSet, Vector};
 use near_sdk::json_types::{ValidAccountId, U128};
 use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::{
     assert_one_yocto, env, near_bindgen, BorshStorageKey, Promise, PromiseResult, StorageUsage,
 };
-use pool::Pool;
+use farming::{Farm, Farmer};
+use flash_loan::FlashLoan;
+use limit_order::LimitOrder;
+use migration::PoolMigration;
+use pool::{Pool, VPool};
+use pool_index::{pool_key, PoolKey};
+use pool_state::PoolState;
+use roles::Role;
 use simple_pool::SimplePool;
-use std::collections::HashMap;
-use utils::check_duplicate_tokens;
-use crate::utils::{ext_self, GAS_FOR_FT_TRANSFER, GAS_FOR_RESOLVE_TRANSFER};
-use crate::account::Account;
+use stable_swap_pool::StableSwapPool;
+use timelock::{AdminProposal, DEFAULT_TIMELOCK_DELAY_SEC};
+use upgrade::StagedUpgrade;
+use weighted_pool::WeightedPool;
+use utils::{check_duplicate_tokens, DEFAULT_FEE_TIERS};
+use crate::account::{Account, VAccount};
+use crate::legacy::AccountV1;
 use crate::actions::Action;
 use crate::errors::*;
+use crate::event::Event;
 
 mod account;
 mod actions;
 mod admin_fee;
 mod errors;
+mod event;
+mod farming;
+mod flash_loan;
+mod legacy;
+mod limit_order;
+mod migration;
+mod multi_fungible_token;
+mod oracle;
 mod owner;
 mod pool;
+mod pool_index;
+mod pool_state;
+mod roles;
+mod router;
 mod simple_pool;
+mod stable_swap_math;
+mod stable_swap_pool;
 mod storage_impl;
+mod timelock;
 mod token_receiver;
+mod upgrade;
 mod utils;
 mod views;
+mod weighted_pool;
+mod wnear;
 
 pub type AccountId = String;
 pub type Balance = u128;
@@ -42,6 +72,25 @@ pub(crate) enum StorageKey {
     Shares { pool_id: u32 },
     Pools,
     Guardian,
+    Observations { pool_id: u32 },
+    FlashLoans,
+    FlashLoanBorrowers,
+    LimitOrders,
+    PoolLimitOrders,
+    Farms,
+    PoolFarms,
+    Farmers,
+    PairPools,
+    TokenPools,
+    Lostfound,
+    AdminProposals,
+    Roles,
+    StagedCode,
+    PoolStates,
+    LimitOrderCounts,
+    TokenPoolIds { token_id: AccountId },
+    VAccounts,
+    PoolKeys,
 }
 
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Eq, PartialEq, Clone)]
@@ -50,12 +99,15 @@ pub(crate) enum StorageKey {
 pub enum RunningState {
     Running,
     Paused,
+    /// Liquidity and deposits can only be withdrawn.
+    WithdrawOnly,
 }
 impl fmt::Display for RunningState {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             RunningState::Running => write!(f, "Running"),
             RunningState::Paused => write!(f, "Paused"),
+            RunningState::WithdrawOnly => write!(f, "WithdrawOnly"),
         }
     }
 }
@@ -67,10 +119,12 @@ pub struct Contract {
     owner_id: AccountId,
 
     /// account with it's information store in Account
-    accounts: LookupMap<AccountId, Account>,
+    accounts: LookupMap<AccountId, VAccount>,
+    /// Accounts stored by the first version, moved to `accounts` on their next save.
+    legacy_accounts: LookupMap<AccountId, AccountV1>,
 
     /// List of all the pools
-    pools: Vector<Pool>,
+    pools: Vector<VPool>,
 
     exchange_fee: u32,
 
@@ -79,10 +133,68 @@ pub struct Contract {
     /// Set of whitelisted tokens by "owner"
     whitelisted_tokens: UnorderedSet<AccountId>,
 
-    /// Set of guardians.
+    /// Set of guardians, who can veto admin proposals.
     guardians: UnorderedSet<AccountId>,
     /// Running state
     state: RunningState,
+
+    /// Outstanding flash loans by pool id.
+    flash_loans: LookupMap<u64, FlashLoan>,
+    /// Accounts allowed to take flash loans.
+    flash_loan_borrowers: UnorderedSet<AccountId>,
+
+    /// Limit orders by id, open ones and filled ones waiting for claim.
+    limit_orders: LookupMap<u64, LimitOrder>,
+    /// Ids of open limit orders by pool id.
+    pool_limit_orders: LookupMap<u64, Vec<u64>>,
+    /// Id of the next placed limit order.
+    next_limit_order_id: u64,
+    /// Number of limit orders by owner, open ones and filled ones waiting for claim.
+    limit_order_counts: LookupMap<AccountId, u32>,
+
+    /// List of all the farms.
+    farms: Vector<Farm>,
+    /// Ids of farms by pool id.
+    pool_farms: LookupMap<u64, Vec<u64>>,
+    /// Rewards state by farm id and account.
+    farmers: LookupMap<(u64, AccountId), Farmer>,
+
+    /// Ids of pools by sorted pair of their tokens.
+    pair_pools: LookupMap<(AccountId, AccountId), Vec<u64>>,
+    /// Ids of pools by their tokens.
+    token_pools: LookupMap<AccountId, Vector<u64>>,
+    /// Id of the pool by its sorted tokens, kind and fee, to keep pools unique.
+    pool_keys: LookupMap<PoolKey, u64>,
+
+    /// Sorted fees (in bps) simple pools can be created with.
+    fee_tiers: Vec<u32>,
+
+    /// Tokens that failed to be sent to accounts, by account and token, claimable by the account.
+    /// Only whitelisted tokens are kept, so an account has at most one entry per whitelisted token.
+    lostfound: LookupMap<(AccountId, AccountId), Balance>,
+
+    /// Wrapped NEAR token contract, used to deposit and withdraw native NEAR.
+    wnear_id: Option<AccountId>,
+
+    /// Proposed admin actions, by id.
+    admin_proposals: Vector<AdminProposal>,
+    /// Delay (in seconds) between proposal and execution of admin actions.
+    timelock_delay_sec: u64,
+
+    /// Roles granted to accounts other than owner.
+    roles: UnorderedMap<AccountId, Vec<Role>>,
+
+    /// Progress of converting pools stored in previous layouts.
+    pool_migration: PoolMigration,
+
+    /// Wasm code staged for upgrade.
+    staged_code: LazyOption<Vec<u8>>,
+    staged_upgrade: Option<StagedUpgrade>,
+    /// Delay (in seconds) between staging and deploying code.
+    upgrade_delay_sec: u64,
+
+    /// States of pools other than running, by pool id.
+    pool_states: LookupMap<u64, PoolState>,
 }
 
 impl Default for Contract {
@@ -91,11 +203,38 @@ impl Default for Contract {
             owner_id: env::predecessor_account_id(),
             exchange_fee: 0,
             referral_fee: 0,
-            accounts: LookupMap::new(StorageKey::Account),
+            accounts: LookupMap::new(StorageKey::VAccounts),
+            legacy_accounts: LookupMap::new(StorageKey::Account),
             pools: Vector::new(StorageKey::Pools),
             whitelisted_tokens: UnorderedSet::new(StorageKey::Whitelist),
             guardians: UnorderedSet::new(StorageKey::Guardian),
             state: RunningState::Running,
+            flash_loans: LookupMap::new(StorageKey::FlashLoans),
+            flash_loan_borrowers: UnorderedSet::new(StorageKey::FlashLoanBorrowers),
+            limit_orders: LookupMap::new(StorageKey::LimitOrders),
+            pool_limit_orders: LookupMap::new(StorageKey::PoolLimitOrders),
+            next_limit_order_id: 0,
+            limit_order_counts: LookupMap::new(StorageKey::LimitOrderCounts),
+            farms: Vector::new(StorageKey::Farms),
+            pool_farms: LookupMap::new(StorageKey::PoolFarms),
+            farmers: LookupMap::new(StorageKey::Farmers),
+            pair_pools: LookupMap::new(StorageKey::PairPools),
+            token_pools: LookupMap::new(StorageKey::TokenPools),
+            pool_keys: LookupMap::new(StorageKey::PoolKeys),
+            fee_tiers: DEFAULT_FEE_TIERS.to_vec(),
+            lostfound: LookupMap::new(StorageKey::Lostfound),
+            wnear_id: None,
+            admin_proposals: Vector::new(StorageKey::AdminProposals),
+            timelock_delay_sec: DEFAULT_TIMELOCK_DELAY_SEC,
+            roles: UnorderedMap::new(StorageKey::Roles),
+            pool_migration: PoolMigration {
+                next_index: 0,
+                end_index: 0,
+            },
+            staged_code: LazyOption::new(StorageKey::StagedCode, None),
+            staged_upgrade: None,
+            upgrade_delay_sec: DEFAULT_TIMELOCK_DELAY_SEC,
+            pool_states: LookupMap::new(StorageKey::PoolStates),
         }
     }
 }
@@ -106,27 +245,97 @@ impl Contract {
     pub fn new() -> Self {
         Self {
             owner_id: env::current_account_id(),
-            accounts: LookupMap::new(StorageKey::Account),
+            accounts: LookupMap::new(StorageKey::VAccounts),
+            legacy_accounts: LookupMap::new(StorageKey::Account),
             exchange_fee: 5,
             referral_fee: 0,
             pools: Vector::new(StorageKey::Pools),
             whitelisted_tokens: UnorderedSet::new(StorageKey::Whitelist),
             guardians: UnorderedSet::new(StorageKey::Guardian),
             state: RunningState::Running,
+            flash_loans: LookupMap::new(StorageKey::FlashLoans),
+            flash_loan_borrowers: UnorderedSet::new(StorageKey::FlashLoanBorrowers),
+            limit_orders: LookupMap::new(StorageKey::LimitOrders),
+            pool_limit_orders: LookupMap::new(StorageKey::PoolLimitOrders),
+            next_limit_order_id: 0,
+            limit_order_counts: LookupMap::new(StorageKey::LimitOrderCounts),
+            farms: Vector::new(StorageKey::Farms),
+            pool_farms: LookupMap::new(StorageKey::PoolFarms),
+            farmers: LookupMap::new(StorageKey::Farmers),
+            pair_pools: LookupMap::new(StorageKey::PairPools),
+            token_pools: LookupMap::new(StorageKey::TokenPools),
+            pool_keys: LookupMap::new(StorageKey::PoolKeys),
+            fee_tiers: DEFAULT_FEE_TIERS.to_vec(),
+            lostfound: LookupMap::new(StorageKey::Lostfound),
+            wnear_id: None,
+            admin_proposals: Vector::new(StorageKey::AdminProposals),
+            timelock_delay_sec: DEFAULT_TIMELOCK_DELAY_SEC,
+            roles: UnorderedMap::new(StorageKey::Roles),
+            pool_migration: PoolMigration {
+                next_index: 0,
+                end_index: 0,
+            },
+            staged_code: LazyOption::new(StorageKey::StagedCode, None),
+            staged_upgrade: None,
+            upgrade_delay_sec: DEFAULT_TIMELOCK_DELAY_SEC,
+            pool_states: LookupMap::new(StorageKey::PoolStates),
         }
     }
 
+    /// Adds constant product pool with `MIN_NUM_TOKENS` up to `MAX_NUM_TOKENS` tokens.
+    /// Fee has to be one of the fee tiers, there can be one pool of the same tokens per fee tier.
     #[payable]
     pub fn add_simple_pool(&mut self, tokens: Vec<ValidAccountId>, fee: u32) -> u64 {
         self.assert_contract_running();
+        assert!(self.fee_tiers.contains(&fee), "{}", ERR88_FEE_TIER_NOT_ALLOWED);
         check_duplicate_tokens(&tokens);
-        self.internal_check_existed_pool(&tokens);
         self.internal_add_pool(Pool::SimplePool(SimplePool::new(
             self.pools.len() as u32,
             tokens,
             fee,
-            0,
-            0,
+        )))
+    }
+
+    /// Adds weighted product pool with given tokens and their weights.
+    /// Weights are normalized, they should sum up to `WEIGHT_DIVISOR` (e.g. 8000 and 2000 for 80/20).
+    /// Only can be called by pool creators.
+    #[payable]
+    pub fn add_weighted_pool(
+        &mut self,
+        tokens: Vec<ValidAccountId>,
+        weights: Vec<u32>,
+        fee: u32,
+    ) -> u64 {
+        self.assert_contract_running();
+        self.assert_role(Role::PoolCreator);
+        check_duplicate_tokens(&tokens);
+        self.internal_add_pool(Pool::WeightedPool(WeightedPool::new(
+            self.pools.len() as u32,
+            tokens,
+            weights,
+            fee,
+        )))
+    }
+
+    /// Adds stable swap pool with given tokens, their decimals and amplification factor.
+    /// Only can be called by pool creators.
+    #[payable]
+    pub fn add_stable_swap_pool(
+        &mut self,
+        tokens: Vec<ValidAccountId>,
+        decimals: Vec<u8>,
+        fee: u32,
+        amp_factor: u64,
+    ) -> u64 {
+        self.assert_contract_running();
+        self.assert_role(Role::PoolCreator);
+        check_duplicate_tokens(&tokens);
+        self.internal_add_pool(Pool::StableSwapPool(StableSwapPool::new(
+            self.pools.len() as u32,
+            tokens,
+            decimals,
+            amp_factor as u128,
+            fee,
         )))
     }
 
@@ -143,12 +352,15 @@ impl Contract {
             env::attached_deposit() > 0,
             "Requires attached deposit of at least 1 yoctoNEAR"
         );
+        self.assert_no_flash_loan(pool_id);
+        self.assert_pool_accepts_liquidity(pool_id);
         let prev_storage = env::storage_usage();
         let sender_id = env::predecessor_account_id();
         let mut amounts: Vec<u128> = amounts.into_iter().map(|amount| amount.into()).collect();
-        let mut pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
+        let mut pool = self.internal_get_pool(pool_id).expect("ERR_NO_POOL");
+        self.internal_checkpoint_farmers(pool_id, &pool, &[&sender_id]);
         // Add amounts given to liquidity first. It will return the balanced amounts.
-        pool.add_liquidity(&sender_id, &mut amounts);
+        let shares = pool.add_liquidity(&sender_id, &mut amounts);
         if let Some(min_amounts) = min_amounts {
             // Check that all amounts are above request min amounts in case of front running that changes the exchange rate.
             for (amount, min_amount) in amounts.iter().zip(min_amounts.iter()) {
@@ -162,18 +374,69 @@ impl Contract {
             deposits_acc.withdraw(&tokens[i], amounts[i]);
         }
         self.internal_save_account(&sender_id, deposits_acc);
-        self.pools.replace(pool_id, &pool);
+        self.internal_save_pool(pool_id, pool);
+        Event::AddLiquidity {
+            account_id: &sender_id,
+            pool_id,
+            amounts: amounts.into_iter().map(U128).collect(),
+            shares: U128(shares),
+        }
+        .emit();
         self.internal_check_storage(prev_storage);
     }
 
+    /// Add liquidity from already deposited amounts to given stable swap pool.
+    /// All given amounts are taken, an imbalance fee applies if they deviate from the pool ratio.
+    /// Returns number of minted shares, which should be at least `min_shares`.
+    #[payable]
+    pub fn add_stable_liquidity(
+        &mut self,
+        pool_id: u64,
+        amounts: Vec<U128>,
+        min_shares: U128,
+    ) -> U128 {
+        self.assert_contract_running();
+        assert!(
+            env::attached_deposit() > 0,
+            "Requires attached deposit of at least 1 yoctoNEAR"
+        );
+        self.assert_no_flash_loan(pool_id);
+        self.assert_pool_accepts_liquidity(pool_id);
+        let prev_storage = env::storage_usage();
+        let sender_id = env::predecessor_account_id();
+        let amounts: Vec<u128> = amounts.into_iter().map(|amount| amount.into()).collect();
+        let mut pool = self.internal_get_pool(pool_id).expect("ERR_NO_POOL");
+        self.internal_checkpoint_farmers(pool_id, &pool, &[&sender_id]);
+        let shares = pool.add_stable_liquidity(&sender_id, &amounts, min_shares.into());
+        let mut deposits_acc = self.internal_unwrap_or_default_account(&sender_id);
+        // Subtract amounts from deposits. This will fail if there is not enough funds for any of the tokens.
+        for (token_id, amount) in pool.tokens().iter().zip(amounts.iter()) {
+            deposits_acc.withdraw(token_id, *amount);
+        }
+        self.internal_save_account(&sender_id, deposits_acc);
+        self.internal_save_pool(pool_id, pool);
+        Event::AddLiquidity {
+            account_id: &sender_id,
+            pool_id,
+            amounts: amounts.into_iter().map(U128).collect(),
+            shares: U128(shares),
+        }
+        .emit();
+        self.internal_check_storage(prev_storage);
+        U128(shares)
+    }
+
     /// Remove liquidity from the pool into general pool of liquidity.
     #[payable]
     pub fn remove_liquidity(&mut self, pool_id: u64, shares: U128, min_amounts: Vec<U128>) {
         assert_one_yocto();
-        self.assert_contract_running();
+        self.assert_contract_withdrawable();
+        // Liquidity can be removed whatever state the pool is in.
         let prev_storage = env::storage_usage();
+        self.assert_no_flash_loan(pool_id);
         let sender_id = env::predecessor_account_id();
-        let mut pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
+        let mut pool = self.internal_get_pool(pool_id).expect("ERR_NO_POOL");
+        self.internal_checkpoint_farmers(pool_id, &pool, &[&sender_id]);
         let amounts = pool.remove_liquidity(
             &sender_id,
             shares.into(),
@@ -182,8 +445,8 @@ impl Contract {
                 .map(|amount| amount.into())
                 .collect(),
         );
-        self.pools.replace(pool_id, &pool);
-        let tokens = pool.tokens();
+        let tokens = pool.tokens().to_vec();
+        self.internal_save_pool(pool_id, pool);
         let mut deposits = self.internal_unwrap_or_default_account(&sender_id);
         for i in 0..tokens.len() {
             deposits.deposit(&tokens[i], amounts[i]);
@@ -194,6 +457,13 @@ impl Contract {
                 (prev_storage - env::storage_usage()) as Balance * env::storage_byte_cost();
         }
         self.internal_save_account(&sender_id, deposits);
+        Event::RemoveLiquidity {
+            account_id: &sender_id,
+            pool_id,
+            shares,
+            amounts: amounts.into_iter().map(U128).collect(),
+        }
+        .emit();
     }
 
     /// [AUDIT_03_reject(NOPE action is allowed by design)]
@@ -226,8 +496,13 @@ impl Contract {
         }
 
         let referral_id = referral_id.map(|r| r.into());
-        let result =
-            self.internal_execute_actions(&mut account, &referral_id, &actions, ActionResult::None);
+        let result = self.internal_execute_actions(
+            &sender_id,
+            &mut account,
+            &referral_id,
+            &actions,
+            ActionResult::None,
+        );
         self.internal_save_account(&sender_id, account);
         result
     }
@@ -240,7 +515,7 @@ impl Contract {
             self.execute_actions(
                 actions
                     .into_iter()
-                    .map(|swap_action| Action::Swap(swap_action))
+                    .map(Action::Swap)
                     .collect(),
                 referral_id,
             )
@@ -250,11 +525,9 @@ impl Contract {
 
     pub fn is_lp(&self, account_id: &ValidAccountId, pool_id: u64) -> bool {
         // let filterd_pools: Vec<SimplePool> = pools.iter().filter(|a|a);
-        self.pools
-            .get(pool_id)
+        self.internal_get_pool(pool_id)
             .expect("ERR_NO_POOL")
             .is_lp(account_id.as_ref())
-            .into()
 
         // for pool in pools.iter() {
         //     if pool.is_lp(&account_id) {
@@ -271,38 +544,63 @@ impl Contract {
     fn assert_contract_running(&self) {
         match self.state {
             RunningState::Running => (),
-            _ => env::panic(ERR51_CONTRACT_PAUSED.as_bytes()),
+            RunningState::Paused => env::panic(ERR51_CONTRACT_PAUSED.as_bytes()),
+            RunningState::WithdrawOnly => env::panic(ERR52_CONTRACT_WITHDRAW_ONLY.as_bytes()),
         };
     }
+
+    /// Panics unless liquidity can be removed, which is also allowed in withdraw only mode.
+    fn assert_contract_withdrawable(&self) {
+        if self.state == RunningState::Paused {
+            env::panic(ERR51_CONTRACT_PAUSED.as_bytes());
+        }
+    }
     // Adds given pool to the list and returns it's id.
     /// If there is not enough attached balance to cover storage, fails.
     /// If too much attached - refunds it back.
     fn internal_add_pool(&mut self, mut pool: Pool) -> u64 {
+        self.internal_check_existed_pool(&pool);
         let prev_storage = env::storage_usage();
-        let id = self.pools.len() as u64;
+        let id = self.pools.len();
         // exchange share was registered at creation time
         pool.share_register(&env::current_account_id());
         pool.share_register(&env::signer_account_id());
-        self.pools.push(&pool);
+        self.internal_index_pool(id, &pool);
+        Event::PoolCreated {
+            pool_id: id,
+            pool_kind: pool.kind(),
+            tokens: pool.tokens(),
+            fee: pool.get_fee(),
+        }
+        .emit();
+        self.pools.push(&pool.into());
         self.internal_check_storage(prev_storage);
         id
     }
 
+    /// Returns given pool, converted to the current version if stored in an old layout.
+    pub(crate) fn internal_get_pool(&self, pool_id: u64) -> Option<Pool> {
+        self.pools.get(pool_id).map(|pool| pool.into_current())
+    }
+
+    pub(crate) fn internal_save_pool(&mut self, pool_id: u64, pool: Pool) {
+        self.pools.replace(pool_id, &pool.into());
+    }
+
     /// Check how much storage taken costs and refund the left over back.
     fn internal_check_storage(&self, prev_storage: StorageUsage) {
-        let storage_cost = env::storage_usage()
-            .checked_sub(prev_storage)
-            .unwrap_or_default() as Balance
-            * env::storage_byte_cost();
+        let storage_cost =
+            env::storage_usage().saturating_sub(prev_storage) as Balance * env::storage_byte_cost();
 
-        let refund = env::attached_deposit().checked_sub(storage_cost).expect(
-            format!(
-                "ERR_STORAGE_DEPOSIT need {}, attatched {}",
-                storage_cost,
-                env::attached_deposit()
-            )
-            .as_str(),
-        );
+        let refund = env::attached_deposit()
+            .checked_sub(storage_cost)
+            .unwrap_or_else(|| {
+                panic!(
+                    "ERR_STORAGE_DEPOSIT need {}, attatched {}",
+                    storage_cost,
+                    env::attached_deposit()
+                )
+            });
         if refund > 0 {
             Promise::new(env::predecessor_account_id()).transfer(refund);
         }
@@ -312,6 +610,7 @@ impl Contract {
     /// Returns result of the last action.
     fn internal_execute_actions(
         &mut self,
+        sender_id: &AccountId,
         account: &mut Account,
         referral_id: &Option<AccountId>,
         actions: &[Action],
@@ -319,7 +618,7 @@ impl Contract {
     ) -> ActionResult {
         let mut result = prev_result;
         for action in actions {
-            result = self.internal_execute_action(account, referral_id, action, result);
+            result = self.internal_execute_action(sender_id, account, referral_id, action, result);
         }
         result
     }
@@ -327,6 +626,7 @@ impl Contract {
     /// Executes single action on given account. Modifies passed account. Returns a result based on type of action.
     fn internal_execute_action(
         &mut self,
+        sender_id: &AccountId,
         account: &mut Account,
         referral_id: &Option<AccountId>,
         action: &Action,
@@ -343,6 +643,7 @@ impl Contract {
                 account.withdraw(&swap_action.token_in, amount_in);
 
                 let amount_out = self.internal_pool_swap(
+                    sender_id,
                     swap_action.pool_id,
                     &swap_action.token_in,
                     amount_in,
@@ -355,13 +656,64 @@ impl Contract {
                 // [AUDIT_02]
                 ActionResult::Amount(U128(amount_out))
             }
+            Action::SwapByOutput(swap_action) => {
+                let amount_in = self
+                    .internal_get_pool(swap_action.pool_id)
+                    .expect("ERR_NO_POOL")
+                    .get_income(
+                        &swap_action.token_in,
+                        &swap_action.token_out,
+                        swap_action.amount_out.0,
+                    );
+                assert!(amount_in <= swap_action.max_amount_in.0, "ERR_MAX_AMOUNT_IN");
+
+                // Take amount of `token_in` out from account to pool.
+                account.withdraw(&swap_action.token_in, amount_in);
+
+                // Rounding of the quote may only make the swap return slightly more than requested.
+                let amount_out = self.internal_pool_swap(
+                    sender_id,
+                    swap_action.pool_id,
+                    &swap_action.token_in,
+                    amount_in,
+                    &swap_action.token_out,
+                    swap_action.amount_out.0,
+                    referral_id,
+                );
+
+                account.deposit(&swap_action.token_out, amount_out);
+                ActionResult::Amount(U128(amount_out))
+            }
+            Action::Route(route_action) => {
+                let amount_in = route_action
+                    .amount_in
+                    .map(|value| value.0)
+                    .unwrap_or_else(|| prev_result.to_amount());
+
+                // Take amount of `token_in` out from account, intermediate tokens never get to it.
+                account.withdraw(&route_action.token_in, amount_in);
+
+                let amount_out = self.internal_route_swap(
+                    sender_id,
+                    &route_action.token_in,
+                    amount_in,
+                    &route_action.token_out,
+                    route_action.min_amount_out.0,
+                    referral_id,
+                );
+
+                account.deposit(&route_action.token_out, amount_out);
+                ActionResult::Amount(U128(amount_out))
+            }
         }
     }
 
     /// Swaps given amount_in of token_in into token_out via given pool.
     /// Should be at least min_amount_out or swap will fail (prevents front running and other slippage issues).
+    #[allow(clippy::too_many_arguments)]
     fn internal_pool_swap(
         &mut self,
+        sender_id: &AccountId,
         pool_id: u64,
         token_in: &AccountId,
         amount_in: u128,
@@ -369,7 +721,17 @@ impl Contract {
         min_amount_out: u128,
         referral_id: &Option<AccountId>,
     ) -> u128 {
-        let mut pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
+        self.assert_no_flash_loan(pool_id);
+        self.assert_pool_migrated(pool_id);
+        self.assert_pool_running(pool_id);
+        let mut pool = self.internal_get_pool(pool_id).expect("ERR_NO_POOL");
+        // Admin fees are minted as shares to the exchange and registered referral.
+        let exchange_id = env::current_account_id();
+        let mut fee_receivers = vec![&exchange_id];
+        if let Some(referral_id) = referral_id.as_ref().filter(|id| pool.is_lp(id)) {
+            fee_receivers.push(referral_id);
+        }
+        self.internal_checkpoint_farmers(pool_id, &pool, &fee_receivers);
         let amount_out = pool.swap(
             token_in,
             amount_in,
@@ -382,19 +744,28 @@ impl Contract {
                 referral_id: referral_id.clone(),
             },
         );
-        self.pools.replace(pool_id, &pool);
+        Event::Swap {
+            account_id: sender_id,
+            pool_id,
+            token_in,
+            amount_in: U128(amount_in),
+            token_out,
+            amount_out: U128(amount_out),
+        }
+        .emit();
+        self.internal_auto_fill_limit_orders(pool_id, &mut pool);
+        self.internal_save_pool(pool_id, pool);
         amount_out
     }
 
-    /// Program will panic if input token pair exsists.
-    fn internal_check_existed_pool(&self, tokens: &Vec<ValidAccountId>) {
-        assert_eq!(tokens.len(), 2, "INVALID NUMBER OF TOKENS");
-
-        let pools = &self.pools;
-        for pool in pools.iter() {
-            let is_existed = pool.check_existed_pool(&tokens);
-            assert!(!is_existed, "THIS POOL PAIR ALREADY EXISTED!!!");
-        }
+    /// Program will panic if pool of the same kind with the tokens, in any order, and fee exists.
+    /// Deprecated pools can be replaced.
+    fn internal_check_existed_pool(&self, pool: &Pool) {
+        let is_existed = self
+            .pool_keys
+            .get(&pool_key(pool))
+            .is_some_and(|pool_id| !self.is_pool_deprecated(pool_id));
+        assert!(!is_existed, "{}", ERR87_POOL_EXISTED);
     }
 }
 
@@ -402,9 +773,16 @@ impl Contract {
 mod tests {
 
     use super::*;
+    use crate::account::INIT_ACCOUNT_STORAGE;
+    use crate::actions::{RouteAction, SwapByOutputAction};
+    use crate::utils::{SwapVolume, INIT_SHARES_SUPPLY};
+    use crate::limit_order::MAX_ORDERS_PER_ACCOUNT;
+    use crate::roles::Role;
+    use crate::timelock::{AdminAction, ProposalStatus};
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
     use near_contract_standards::storage_management::StorageManagement;
-    use near_sdk::test_utils::{accounts, VMContextBuilder};
-    use near_sdk::{testing_env, Balance, BlockchainInterface, MockedBlockchain};
+    use near_sdk::test_utils::{accounts, get_logs, VMContextBuilder};
+    use near_sdk::{testing_env, Balance, MockedBlockchain, PromiseOrValue};
 
     const ONE_NEAR: u128 = 1_000_000_000_000_000_000_000_000;
 
@@ -440,6 +818,7 @@ mod tests {
                 .accounts
                 .get(&account_id.to_string())
                 .unwrap()
+                .into_current()
                 .tokens
                 .get(&token_id.to_string())
                 .unwrap()
@@ -448,9 +827,1583 @@ mod tests {
         )
     }
     #[test]
-    #[should_panic("ERR_TRANSFER_AMOUNT_EQUAL_ZERO")]
+    fn test_multi_token_pool() {
+        let (_, mut contract) = setup_contract();
+        let tokens = vec![accounts(2), accounts(3), accounts(4)];
+        contract.storage_deposit(None, None);
+        for token in tokens.iter() {
+            contract.internal_transfer_from_user(
+                &accounts(0).to_string(),
+                &token.to_string(),
+                1_000_000,
+            );
+        }
+        let pool_id = contract.add_simple_pool(tokens.clone(), 30);
+        contract.add_liquidity(pool_id, vec![U128(100_000); 3], None);
+        assert_eq!(contract.get_pool(pool_id).amounts, vec![U128(100_000); 3]);
+
+        let amount_out = contract.swap(
+            vec![SwapAction {
+                pool_id,
+                token_in: accounts(3).to_string(),
+                amount_in: Some(U128(1_000)),
+                token_out: accounts(4).to_string(),
+                min_amount_out: U128(1),
+            }],
+            None,
+        );
+        assert_eq!(
+            contract.get_pool(pool_id).amounts,
+            vec![U128(100_000), U128(101_000), U128(100_000 - amount_out.0)]
+        );
+        assert_eq!(
+            contract.get_account_balance(accounts(0).to_string(), accounts(4).to_string()),
+            U128(900_000 + amount_out.0)
+        );
+
+        let amounts =
+            contract.predict_remove_liquidity(pool_id, contract.get_pool_total_shares(pool_id));
+        assert_eq!(amounts.len(), 3);
+    }
+
+    #[test]
+    fn test_weighted_pool() {
+        let (_, mut contract) = setup_contract();
+        contract.storage_deposit(None, None);
+        for token in [accounts(2), accounts(3)].iter() {
+            contract.internal_transfer_from_user(
+                &accounts(0).to_string(),
+                &token.to_string(),
+                ONE_NEAR,
+            );
+        }
+        contract.owner_id = accounts(0).into();
+        let pool_id =
+            contract.add_weighted_pool(vec![accounts(2), accounts(3)], vec![8000, 2000], 0);
+        // 80/20 pool priced 1:1 holds 4 times more of the first token.
+        contract.add_liquidity(pool_id, vec![U128(800_000_000), U128(200_000_000)], None);
+        assert_eq!(contract.get_pool(pool_id).weights, Some(vec![8000, 2000]));
+
+        let amount_in = 10_000_000u128;
+        let amount_out = contract
+            .get_return(pool_id, accounts(2), U128(amount_in), accounts(3))
+            .0;
+        let expected = 200_000_000f64 * (1f64 - (800_000_000f64 / 810_000_000f64).powi(4));
+        assert!((amount_out as f64) <= expected && (amount_out as f64) > expected * 0.999_999);
+    }
+
+    #[test]
+    #[should_panic(expected = "E73: swap amount exceeds max ratio of pool balance")]
+    fn test_weighted_pool_max_out_ratio() {
+        let (_, mut contract) = setup_contract();
+        contract.storage_deposit(None, None);
+        for token in [accounts(2), accounts(3)].iter() {
+            contract.internal_transfer_from_user(
+                &accounts(0).to_string(),
+                &token.to_string(),
+                ONE_NEAR,
+            );
+        }
+        contract.owner_id = accounts(0).into();
+        let pool_id =
+            contract.add_weighted_pool(vec![accounts(2), accounts(3)], vec![8000, 2000], 0);
+        contract.add_liquidity(pool_id, vec![U128(800_000_000), U128(200_000_000)], None);
+        // A quarter of the heavy token's balance takes out more than half of the light one.
+        contract.swap(
+            vec![SwapAction {
+                pool_id,
+                token_in: accounts(2).to_string(),
+                amount_in: Some(U128(200_000_000)),
+                token_out: accounts(3).to_string(),
+                min_amount_out: U128(1),
+            }],
+            None,
+        );
+    }
+
+    #[test]
+    fn test_swap_by_output() {
+        let (_, mut contract) = setup_contract();
+        contract.storage_deposit(None, None);
+        for token in [accounts(2), accounts(3)].iter() {
+            contract.internal_transfer_from_user(
+                &accounts(0).to_string(),
+                &token.to_string(),
+                1_000_000,
+            );
+        }
+        let pool_id = contract.add_simple_pool(vec![accounts(2), accounts(3)], 30);
+        contract.add_liquidity(pool_id, vec![U128(100_000), U128(100_000)], None);
+
+        let amount_in = contract
+            .get_income(pool_id, accounts(2), U128(10_000), accounts(3))
+            .0;
+        // Fee free curve would need 11_112, fee makes it more expensive.
+        assert!(amount_in > 11_112);
+        assert!(
+            contract
+                .get_return(pool_id, accounts(2), U128(amount_in), accounts(3))
+                .0
+                >= 10_000
+        );
+        assert!(
+            contract
+                .get_return(pool_id, accounts(2), U128(amount_in - 2), accounts(3))
+                .0
+                < 10_000
+        );
+
+        let result = contract.execute_actions(
+            vec![Action::SwapByOutput(SwapByOutputAction {
+                pool_id,
+                token_in: accounts(2).to_string(),
+                amount_out: U128(10_000),
+                token_out: accounts(3).to_string(),
+                max_amount_in: U128(amount_in),
+            })],
+            None,
+        );
+        assert!(result.to_amount() >= 10_000);
+        assert_eq!(
+            contract.get_account_balance(accounts(0).to_string(), accounts(2).to_string()),
+            U128(900_000 - amount_in)
+        );
+    }
+
+    #[test]
+    fn test_share_price() {
+        let (_, mut contract) = setup_contract();
+        contract.storage_deposit(None, None);
+        for token in [accounts(2), accounts(3)].iter() {
+            contract.internal_transfer_from_user(
+                &accounts(0).to_string(),
+                &token.to_string(),
+                10 * ONE_NEAR,
+            );
+        }
+        let pool_id = contract.add_simple_pool(vec![accounts(2), accounts(3)], 30);
+        assert_eq!(contract.get_pool_share_price(pool_id), U128(0));
+        contract.add_liquidity(pool_id, vec![U128(ONE_NEAR), U128(ONE_NEAR)], None);
+        assert_eq!(contract.get_pool_share_price(pool_id), U128(2 * ONE_NEAR));
+        assert_eq!(
+            contract.get_pool_share_breakdown(pool_id),
+            vec![U128(ONE_NEAR), U128(ONE_NEAR)]
+        );
+
+        // Whole share of a pool of 6 decimal tokens is worth its first token in those decimals.
+        let pool_id = contract.add_simple_pool(vec![accounts(2), accounts(3)], 5);
+        contract.add_liquidity(pool_id, vec![U128(1_000_000), U128(1_000_000)], None);
+        assert_eq!(contract.get_pool_share_price(pool_id), U128(2_000_000));
+
+        contract.owner_id = accounts(0).into();
+        let pool_id =
+            contract.add_weighted_pool(vec![accounts(2), accounts(3)], vec![8000, 2000], 0);
+        contract.add_liquidity(
+            pool_id,
+            vec![U128(8 * ONE_NEAR / 10), U128(2 * ONE_NEAR / 10)],
+            None,
+        );
+        assert_eq!(contract.get_pool_share_price(pool_id), U128(ONE_NEAR));
+
+        // Balanced stable pools are priced in the decimals of their first token too.
+        let pool_id =
+            contract.add_stable_swap_pool(vec![accounts(2), accounts(3)], vec![6, 24], 25, 100);
+        contract.add_stable_liquidity(pool_id, vec![U128(1_000_000), U128(ONE_NEAR)], U128(1));
+        assert_eq!(contract.get_pool_share_price(pool_id), U128(1_000_000));
+        assert_eq!(
+            contract.get_pool_share_breakdown(pool_id),
+            vec![U128(500_000), U128(ONE_NEAR / 2)]
+        );
+
+        let pool_id =
+            contract.add_stable_swap_pool(vec![accounts(2), accounts(3)], vec![24, 24], 5, 100);
+        contract.add_stable_liquidity(pool_id, vec![U128(ONE_NEAR), U128(ONE_NEAR)], U128(1));
+        assert_eq!(contract.get_pool_share_price(pool_id), U128(ONE_NEAR));
+    }
+
+    #[test]
+    fn test_twap() {
+        let (mut context, mut contract) = setup_contract();
+        let second = 1_000_000_000;
+        testing_env!(context.block_timestamp(1_000 * second).build());
+        contract.storage_deposit(None, None);
+        for token in [accounts(2), accounts(3)].iter() {
+            contract.internal_transfer_from_user(
+                &accounts(0).to_string(),
+                &token.to_string(),
+                1_000_000,
+            );
+        }
+        let pool_id = contract.add_simple_pool(vec![accounts(2), accounts(3)], 30);
+        contract.add_liquidity(pool_id, vec![U128(100_000), U128(100_000)], None);
+
+        testing_env!(context.block_timestamp(2_000 * second).build());
+        contract.swap(
+            vec![SwapAction {
+                pool_id,
+                token_in: accounts(2).to_string(),
+                amount_in: Some(U128(10_000)),
+                token_out: accounts(3).to_string(),
+                min_amount_out: U128(1),
+            }],
+            None,
+        );
+        let amounts = contract.get_pool(pool_id).amounts;
+        let price = amounts[1].0 * 1_000_000_000_000_000_000 / amounts[0].0;
+
+        // Half of the window at 1:1, the other half at the price after swap.
+        testing_env!(context.block_timestamp(3_000 * second).build());
+        assert_eq!(
+            contract.get_twap(pool_id, accounts(2), accounts(3), 1_500),
+            U128((1_000_000_000_000_000_000 + price) / 2)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E86: operation not supported by this pool kind")]
+    fn test_twap_multi_token_pool() {
+        let (_, mut contract) = setup_contract();
+        let pool_id = contract.add_simple_pool(vec![accounts(2), accounts(3), accounts(4)], 30);
+        contract.get_twap(pool_id, accounts(2), accounts(3), 60);
+    }
+
+    #[test]
+    #[should_panic(expected = "E90: illegal twap window")]
+    fn test_twap_window_overflow() {
+        let (_, mut contract) = setup_contract();
+        let pool_id = contract.add_simple_pool(vec![accounts(2), accounts(3)], 30);
+        contract.get_twap(pool_id, accounts(2), accounts(3), u64::MAX);
+    }
+
+    #[test]
+    fn test_flash_loan() {
+        let (mut context, mut contract) = setup_contract();
+        contract.storage_deposit(None, None);
+        for token in [accounts(2), accounts(3)].iter() {
+            contract.internal_transfer_from_user(
+                &accounts(0).to_string(),
+                &token.to_string(),
+                1_000_000,
+            );
+        }
+        let pool_id = contract.add_simple_pool(vec![accounts(2), accounts(3)], 30);
+        contract.add_liquidity(pool_id, vec![U128(100_000), U128(100_000)], None);
+
+        // Owner calls on behalf of the contract account, so the callback passes as private too.
+        testing_env!(context
+            .current_account_id(accounts(0))
+            .attached_deposit(1)
+            .build());
+        contract.owner_id = accounts(0).into();
+        contract.extend_flash_loan_borrowers(vec![accounts(0)]);
+        contract.flash_loan(pool_id, accounts(2), U128(50_000), accounts(1), "".to_string());
+        assert_eq!(contract.get_pool(pool_id).amounts[0], U128(50_000));
+        assert_eq!(
+            contract.get_account_balance(accounts(0).to_string(), accounts(2).to_string()),
+            U128(900_000)
+        );
+
+        // Receiver repays a bit more than loan plus 150 of fee.
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.ft_on_transfer(
+            accounts(1),
+            U128(50_200),
+            "{\"repay_flash_loan\": 0}".to_string(),
+        );
+
+        // Whole loan was used, the excess is returned to the borrower.
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(b"\"50000\"".to_vec())]
+        );
+        contract.callback_flash_loan(pool_id);
+        assert!(get_logs().last().unwrap().ends_with(
+            "\"event\":\"flash_loan\",\"data\":{\"pool_id\":0,\"borrower_id\":\"alice\",\
+            \"token_id\":\"charlie\",\"amount\":\"50000\",\"used\":\"50000\",\"required\":\"50150\",\
+            \"repaid\":\"50200\",\"charged\":\"0\"}}"
+        ));
+        assert_eq!(contract.get_pool(pool_id).amounts[0], U128(100_150));
+        assert_eq!(
+            contract.get_account_balance(accounts(0).to_string(), accounts(2).to_string()),
+            U128(900_000 + 50)
+        );
+    }
+
+    #[test]
+    fn test_flash_loan_shortfall() {
+        let (mut context, mut contract) = setup_contract();
+        contract.storage_deposit(None, None);
+        for token in [accounts(2), accounts(3)].iter() {
+            contract.internal_transfer_from_user(
+                &accounts(0).to_string(),
+                &token.to_string(),
+                100_000,
+            );
+        }
+        let pool_id = contract.add_simple_pool(vec![accounts(2), accounts(3)], 30);
+        contract.add_liquidity(pool_id, vec![U128(99_990), U128(99_990)], None);
+
+        // Borrower doesn't need deposits covering the loan.
+        testing_env!(context
+            .current_account_id(accounts(0))
+            .attached_deposit(1)
+            .build());
+        contract.owner_id = accounts(0).into();
+        contract.extend_flash_loan_borrowers(vec![accounts(0)]);
+        contract.flash_loan(pool_id, accounts(2), U128(50_000), accounts(1), "".to_string());
+
+        // Receiver repays 50 less than loan plus 150 of fee, borrower only has 10 in deposits.
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.ft_on_transfer(
+            accounts(1),
+            U128(50_100),
+            "{\"repay_flash_loan\": 0}".to_string(),
+        );
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(b"\"50000\"".to_vec())]
+        );
+        assert_eq!(contract.callback_flash_loan(pool_id), U128(50_110));
+        assert_eq!(contract.get_pool(pool_id).amounts[0], U128(100_100));
+        assert_eq!(
+            contract.get_account_balance(accounts(0).to_string(), accounts(2).to_string()),
+            U128(0)
+        );
+        assert!(contract.get_flash_loan_borrowers().is_empty());
+    }
+
+    #[test]
+    fn test_limit_order() {
+        let (mut context, mut contract) = setup_contract();
+        contract.storage_deposit(None, None);
+        for token in [accounts(2), accounts(3)].iter() {
+            contract.internal_transfer_from_user(
+                &accounts(0).to_string(),
+                &token.to_string(),
+                1_000_000,
+            );
+        }
+        let pool_id = contract.add_simple_pool(vec![accounts(2), accounts(3)], 30);
+        contract.add_liquidity(pool_id, vec![U128(100_000), U128(100_000)], None);
+
+        // Sell 1_000 of the second token for at least 1_100 of the first one.
+        let order_id =
+            contract.place_limit_order(pool_id, accounts(3), U128(1_000), accounts(2), U128(1_100));
+        assert_eq!(
+            contract.get_account_balance(accounts(0).to_string(), accounts(3).to_string()),
+            U128(899_000)
+        );
+        assert_eq!(contract.get_pool_limit_orders(pool_id).len(), 1);
+
+        // Buying the second token pushes its price above the limit.
+        contract.swap(
+            vec![SwapAction {
+                pool_id,
+                token_in: accounts(2).to_string(),
+                amount_in: Some(U128(20_000)),
+                token_out: accounts(3).to_string(),
+                min_amount_out: U128(1),
+            }],
+            None,
+        );
+        let amount_out = contract
+            .get_limit_order(order_id)
+            .unwrap()
+            .amount_out
+            .unwrap()
+            .0;
+        assert!(amount_out >= 1_100);
+        assert!(contract.get_pool_limit_orders(pool_id).is_empty());
+
+        let balance =
+            contract.get_account_balance(accounts(0).to_string(), accounts(2).to_string());
+        testing_env!(context.attached_deposit(1).build());
+        contract.claim_limit_order(order_id);
+        assert_eq!(
+            contract.get_account_balance(accounts(0).to_string(), accounts(2).to_string()),
+            U128(balance.0 + amount_out)
+        );
+        assert!(contract.get_limit_order(order_id).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "E115: too many limit orders of this account")]
+    fn test_limit_order_account_cap() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context.attached_deposit(5 * ONE_NEAR).build());
+        contract.storage_deposit(None, None);
+        for token in [accounts(2), accounts(3)].iter() {
+            contract.internal_transfer_from_user(
+                &accounts(0).to_string(),
+                &token.to_string(),
+                1_000_000,
+            );
+        }
+        let pool_id = contract.add_simple_pool(vec![accounts(2), accounts(3)], 30);
+        contract.add_liquidity(pool_id, vec![U128(100_000), U128(100_000)], None);
+
+        // Orders are kept from the lowest limit price.
+        contract.place_limit_order(pool_id, accounts(3), U128(1_000), accounts(2), U128(1_100));
+        contract.place_limit_order(pool_id, accounts(3), U128(1_000), accounts(2), U128(1_050));
+        let orders = contract.get_pool_limit_orders(pool_id);
+        assert_eq!(orders[0].min_amount_out, U128(1_050));
+        assert_eq!(orders[1].min_amount_out, U128(1_100));
+
+        for _ in 0..MAX_ORDERS_PER_ACCOUNT - 1 {
+            contract.place_limit_order(pool_id, accounts(3), U128(1_000), accounts(2), U128(1_200));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "E75: pool is not running")]
+    fn test_limit_order_pool_not_running() {
+        let (mut context, mut contract) = setup_contract();
+        contract.owner_id = accounts(0).into();
+        let pool_id = setup_pool_with_liquidity(&mut contract);
+        testing_env!(context.attached_deposit(1).build());
+        contract.set_pool_state(pool_id, PoolState::WithdrawOnly);
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.place_limit_order(pool_id, accounts(3), U128(1_000), accounts(2), U128(1_100));
+    }
+
+    #[test]
+    fn test_farming() {
+        let (mut context, mut contract) = setup_contract();
+        let second = 1_000_000_000;
+        testing_env!(context
+            .current_account_id(accounts(5))
+            .block_timestamp(1_000 * second)
+            .build());
+        contract.storage_deposit(None, None);
+        for token in [accounts(2), accounts(3), accounts(4)].iter() {
+            contract.internal_transfer_from_user(
+                &accounts(0).to_string(),
+                &token.to_string(),
+                1_000_000,
+            );
+        }
+        let pool_id = contract.add_simple_pool(vec![accounts(2), accounts(3)], 30);
+        contract.add_liquidity(pool_id, vec![U128(100_000), U128(100_000)], None);
+        contract.owner_id = accounts(0).into();
+        let farm_id = contract.create_farm(pool_id, accounts(4), U128(100), 1_000, 1_100);
+        assert_eq!(
+            contract.get_account_balance(accounts(0).to_string(), accounts(4).to_string()),
+            U128(990_000)
+        );
+        // Admin fee shares of the exchange don't take a part of the rewards.
+        contract.swap(
+            vec![SwapAction {
+                pool_id,
+                token_in: accounts(2).to_string(),
+                amount_in: Some(U128(10_000)),
+                token_out: accounts(3).to_string(),
+                min_amount_out: U128(1),
+            }],
+            None,
+        );
+        assert!(contract.get_account_shares_in_pool(pool_id, accounts(5)).0 > 0);
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.storage_deposit(None, None);
+        contract.mft_register(format!(":{}", pool_id), accounts(1));
+
+        // Half of the shares move to another LP in the middle of the farm.
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(1_050 * second)
+            .attached_deposit(1)
+            .build());
+        let shares = contract.get_account_shares_in_pool(pool_id, accounts(0)).0;
+        contract.mft_transfer(format!(":{}", pool_id), accounts(1), U128(shares / 2), None);
+
+        testing_env!(context
+            .block_timestamp(1_200 * second)
+            .attached_deposit(0)
+            .build());
+        assert_eq!(
+            contract.get_unclaimed_farm_rewards(farm_id, accounts(1)),
+            U128(2_500)
+        );
+        assert_eq!(contract.claim_farm_rewards(farm_id), U128(7_500));
+        assert_eq!(
+            contract.get_account_balance(accounts(0).to_string(), accounts(4).to_string()),
+            U128(997_500)
+        );
+        assert_eq!(contract.withdraw_farm_leftover(farm_id), U128(0));
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        assert_eq!(contract.claim_farm_rewards(farm_id), U128(2_500));
+    }
+
+    #[test]
+    fn test_events() {
+        let (_, mut contract) = setup_contract();
+        contract.storage_deposit(None, None);
+        for token in [accounts(2), accounts(3)].iter() {
+            contract.internal_transfer_from_user(
+                &accounts(0).to_string(),
+                &token.to_string(),
+                1_000_000,
+            );
+        }
+        let pool_id = contract.add_simple_pool(vec![accounts(2), accounts(3)], 30);
+        contract.add_liquidity(pool_id, vec![U128(100_000), U128(100_000)], None);
+        let amount_out = contract.swap(
+            vec![SwapAction {
+                pool_id,
+                token_in: accounts(2).to_string(),
+                amount_in: Some(U128(1_000)),
+                token_out: accounts(3).to_string(),
+                min_amount_out: U128(1),
+            }],
+            None,
+        );
+        assert_eq!(
+            get_logs().last().unwrap(),
+            &format!(
+                "EVENT_JSON:{{\"standard\":\"forbitswap\",\"version\":\"1.4.0\",\"event\":\"swap\",\"data\":\
+                {{\"account_id\":\"alice\",\"pool_id\":0,\"token_in\":\"charlie\",\"amount_in\":\"1000\",\
+                \"token_out\":\"danny\",\"amount_out\":\"{}\"}}}}",
+                amount_out.0
+            )
+        );
+    }
+
+    #[test]
+    fn test_pools_by_pair() {
+        let (_, mut contract) = setup_contract();
+        let pool0 = contract.add_simple_pool(vec![accounts(2), accounts(3)], 30);
+        let pool1 = contract.add_simple_pool(vec![accounts(3), accounts(2)], 5);
+        let pool2 = contract.add_simple_pool(vec![accounts(2), accounts(3), accounts(4)], 30);
+        assert_eq!(
+            contract.get_pools_by_pair(accounts(3), accounts(2)),
+            vec![pool0, pool1, pool2]
+        );
+        assert_eq!(contract.get_pools_by_pair(accounts(4), accounts(3)), vec![pool2]);
+        assert!(contract.get_pools_by_pair(accounts(4), accounts(1)).is_empty());
+        assert_eq!(contract.get_pools_by_token(accounts(4), 0, 10), vec![pool2]);
+        assert_eq!(
+            contract.get_pools_by_token(accounts(2), 0, 10),
+            vec![pool0, pool1, pool2]
+        );
+        assert_eq!(contract.get_pools_by_token(accounts(2), 1, 1), vec![pool1]);
+    }
+
+    #[test]
+    fn test_route() {
+        let (_, mut contract) = setup_contract();
+        contract.storage_deposit(None, None);
+        for token in [accounts(2), accounts(3), accounts(4)].iter() {
+            contract.internal_transfer_from_user(
+                &accounts(0).to_string(),
+                &token.to_string(),
+                10_000_000,
+            );
+        }
+        let pool0 = contract.add_simple_pool(vec![accounts(2), accounts(3)], 5);
+        let pool1 = contract.add_simple_pool(vec![accounts(2), accounts(3)], 30);
+        let pool2 = contract.add_simple_pool(vec![accounts(3), accounts(4)], 30);
+        for pool_id in [pool0, pool1, pool2] {
+            contract.add_liquidity(pool_id, vec![U128(1_000_000), U128(1_000_000)], None);
+        }
+
+        // Large swap is split between parallel pools.
+        let quote = contract.quote_best_route(accounts(2), accounts(3), U128(400_000));
+        assert_eq!(quote.splits.len(), 2);
+        assert!(
+            quote.amount_out.0
+                > contract
+                    .get_return(pool0, accounts(2), U128(400_000), accounts(3))
+                    .0
+        );
+
+        // Only one of the parallel pools can be used in front of the shared one.
+        let quote = contract.quote_best_route(accounts(2), accounts(4), U128(10_000));
+        assert_eq!(quote.splits.len(), 1);
+        let hops: Vec<u64> = quote.splits[0].hops.iter().map(|hop| hop.pool_id).collect();
+        assert_eq!(hops, vec![pool0, pool2]);
+
+        let result = contract.execute_actions(
+            vec![Action::Route(RouteAction {
+                token_in: accounts(2).to_string(),
+                amount_in: Some(U128(10_000)),
+                token_out: accounts(4).to_string(),
+                min_amount_out: quote.amount_out,
+            })],
+            None,
+        );
+        assert_eq!(result.to_amount(), quote.amount_out.0);
+        assert_eq!(
+            contract.get_account_balance(accounts(0).to_string(), accounts(4).to_string()),
+            U128(9_000_000 + quote.amount_out.0)
+        );
+    }
+
+    #[test]
+    fn test_route_action_unknown_fields() {
+        let route =
+            "{\"token_in\": \"charlie\", \"token_out\": \"danny\", \"min_amount_out\": \"1\"}";
+        assert!(matches!(
+            near_sdk::serde_json::from_str(route).unwrap(),
+            Action::Route(_)
+        ));
+        // Swap action with a misspelled pool id.
+        let swap = "{\"poolid\": 0, \"token_in\": \"charlie\", \"token_out\": \"danny\", \
+            \"min_amount_out\": \"1\"}";
+        assert!(near_sdk::serde_json::from_str::<Action>(swap).is_err());
+    }
+
+    #[test]
+    fn test_swap_by_output_action_unknown_fields() {
+        let swap = "{\"pool_id\": 0, \"token_in\": \"charlie\", \"amount_out\": \"10\", \
+            \"token_out\": \"danny\", \"max_amount_in\": \"100\"}";
+        assert!(matches!(
+            near_sdk::serde_json::from_str(swap).unwrap(),
+            Action::SwapByOutput(_)
+        ));
+        // Swap by output with an unknown field.
+        let swap = "{\"pool_id\": 0, \"token_in\": \"charlie\", \"amount_out\": \"10\", \
+            \"token_out\": \"danny\", \"max_amount_in\": \"100\", \"deadline\": 0}";
+        assert!(near_sdk::serde_json::from_str::<Action>(swap).is_err());
+        // Swap by output with minimum amount out instead of maximum amount in would be a swap
+        // of whole output of the previous action.
+        let swap = "{\"pool_id\": 0, \"token_in\": \"charlie\", \"amount_out\": \"10\", \
+            \"token_out\": \"danny\", \"min_amount_out\": \"100\"}";
+        assert!(near_sdk::serde_json::from_str::<Action>(swap).is_err());
+    }
+
+    #[test]
+    fn test_transfer_call_add_liquidity() {
+        let (mut context, mut contract) = setup_contract();
+        contract.storage_deposit(None, None);
+        contract.storage_deposit(Some(accounts(1)), None);
+        for token in [accounts(2), accounts(3)].iter() {
+            contract.internal_transfer_from_user(
+                &accounts(0).to_string(),
+                &token.to_string(),
+                1_000_000,
+            );
+        }
+        let pool_id = contract.add_simple_pool(vec![accounts(2), accounts(3)], 30);
+        contract.add_liquidity(pool_id, vec![U128(1_000_000), U128(1_000_000)], None);
+        let total_shares = contract.get_pool_total_shares(pool_id).0;
+        contract.owner_id = accounts(0).into();
+        contract.extend_whitelisted_tokens(vec![accounts(2)]);
+
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        let unused = contract.ft_on_transfer(
+            accounts(0),
+            U128(100_000),
+            format!("{{\"pool_id\": {}, \"min_shares\": \"1\"}}", pool_id),
+        );
+        let unused = match unused {
+            PromiseOrValue::Value(unused) => unused.0,
+            _ => unreachable!(),
+        };
+        // Swapped part and the rest are added almost completely.
+        assert!(unused < 10);
+        let shares = contract.get_pool_total_shares(pool_id).0 - total_shares;
+        assert!(shares > total_shares * 4 / 100);
+        assert_eq!(contract.get_pool(pool_id).amounts[0], U128(1_100_000 - unused));
+
+        contract.ft_on_transfer(
+            accounts(0),
+            U128(1_000),
+            format!("{{\"account_id\": \"{}\"}}", accounts(1)),
+        );
+        assert_eq!(
+            contract.get_account_balance(accounts(1).to_string(), accounts(2).to_string()),
+            U128(1_000)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E141: non-whitelisted token can't be kept as lost and found")]
+    fn test_instant_swap_not_whitelisted() {
+        let (mut context, mut contract) = setup_contract();
+        contract.storage_deposit(None, None);
+        for token in [accounts(2), accounts(3)].iter() {
+            contract.internal_transfer_from_user(
+                &accounts(0).to_string(),
+                &token.to_string(),
+                1_000_000,
+            );
+        }
+        let pool_id = contract.add_simple_pool(vec![accounts(2), accounts(3)], 30);
+        contract.add_liquidity(pool_id, vec![U128(1_000_000), U128(1_000_000)], None);
+
+        // Output can't be kept for an unregistered sender if the transfer fails,
+        // so the swap fails and the token contract refunds the input.
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.ft_on_transfer(
+            accounts(1),
+            U128(1_000),
+            format!(
+                "{{\"actions\": [{{\"pool_id\": {}, \"token_in\": \"{}\", \"amount_in\": \"1000\", \
+                \"token_out\": \"{}\", \"min_amount_out\": \"1\"}}]}}",
+                pool_id,
+                accounts(2),
+                accounts(3)
+            ),
+        );
+    }
+
+    #[test]
+    fn test_instant_swap_refund() {
+        let (mut context, mut contract) = setup_contract();
+        contract.storage_deposit(None, None);
+        for token in [accounts(2), accounts(3)].iter() {
+            contract.whitelisted_tokens.insert(&token.to_string());
+            contract.internal_transfer_from_user(
+                &accounts(0).to_string(),
+                &token.to_string(),
+                1_000_000,
+            );
+        }
+        let pool_id = contract.add_simple_pool(vec![accounts(2), accounts(3)], 30);
+        contract.add_liquidity(pool_id, vec![U128(1_000_000), U128(1_000_000)], None);
+
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        let refund = contract.ft_on_transfer(
+            accounts(1),
+            U128(2_000),
+            format!(
+                "{{\"actions\": [{{\"pool_id\": {}, \"token_in\": \"{}\", \"amount_in\": \"1000\", \
+                \"token_out\": \"{}\", \"min_amount_out\": \"1\"}}]}}",
+                pool_id,
+                accounts(2),
+                accounts(3)
+            ),
+        );
+        assert!(matches!(refund, PromiseOrValue::Value(U128(1_000))));
+        assert_eq!(contract.get_pool(pool_id).amounts[0], U128(1_001_000));
+
+        // Failed transfer to unregistered account goes to lost and found.
+        testing_env!(
+            context
+                .predecessor_account_id(accounts(0))
+                .current_account_id(accounts(0))
+                .build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.exchange_callback_post_withdraw(
+            accounts(3).to_string(),
+            accounts(1).to_string(),
+            U128(500),
+        );
+        assert!(get_logs()[0].contains("\"event\":\"withdraw_failed\""));
+        assert_eq!(
+            contract.get_lostfound(accounts(1)).get(accounts(3).as_ref()),
+            Some(&U128(500))
+        );
+
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(1)
+            .build());
+        contract.claim_lostfound(accounts(3));
+        assert!(contract.get_lostfound(accounts(1)).is_empty());
+    }
+
+    #[test]
+    fn test_near_deposit() {
+        let (mut context, mut contract) = setup_contract();
+        contract.storage_deposit(None, None);
+        contract.owner_id = accounts(0).into();
+        testing_env!(context
+            .current_account_id(accounts(0))
+            .attached_deposit(1)
+            .build());
+        let proposal_id = contract.propose_admin_action(AdminAction::SetWnearId {
+            wnear_id: accounts(5),
+        });
+        assert!(contract.get_wnear_id().is_none());
+        testing_env!(context
+            .block_timestamp(DEFAULT_TIMELOCK_DELAY_SEC * 1_000_000_000)
+            .build());
+        contract.execute_admin_action(proposal_id);
+        assert_eq!(contract.get_wnear_id(), Some(accounts(5).into()));
+
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.near_deposit();
+        testing_env!(
+            context.attached_deposit(0).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        contract.callback_near_deposit(accounts(5).into(), accounts(0).into(), U128(ONE_NEAR));
+        assert!(get_logs()[0].contains("\"event\":\"deposit\""));
+        assert_eq!(
+            contract.get_account_balance(accounts(0).into(), accounts(5).into()),
+            U128(ONE_NEAR)
+        );
+
+        testing_env!(context.attached_deposit(1).build());
+        contract.withdraw_near(U128(ONE_NEAR / 4));
+        assert_eq!(
+            contract.get_account_balance(accounts(0).into(), accounts(5).into()),
+            U128(ONE_NEAR * 3 / 4)
+        );
+        // Unwrapping failed, wNEAR goes back to deposits.
+        testing_env!(
+            context.attached_deposit(0).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.callback_near_withdraw(accounts(5).into(), accounts(0).into(), U128(ONE_NEAR / 4));
+        assert_eq!(
+            contract.get_account_balance(accounts(0).into(), accounts(5).into()),
+            U128(ONE_NEAR)
+        );
+    }
+
+    #[test]
+    fn test_admin_timelock() {
+        let (mut context, mut contract) = setup_contract();
+        let second = 1_000_000_000;
+        contract.owner_id = accounts(0).into();
+        contract.guardians.insert(&accounts(1).into());
+        testing_env!(context
+            .block_timestamp(1_000 * second)
+            .attached_deposit(1)
+            .build());
+        let fee_id = contract.propose_admin_action(AdminAction::ModifyAdminFee {
+            exchange_fee: 10,
+            referral_fee: 2,
+        });
+        let owner_id = contract.propose_admin_action(AdminAction::SetOwner {
+            owner_id: accounts(2),
+        });
+        let proposal = contract.get_admin_proposal(fee_id).unwrap();
+        assert_eq!(proposal.executable_at, 1_000 + DEFAULT_TIMELOCK_DELAY_SEC);
+        assert_eq!(proposal.status, ProposalStatus::Pending);
+        assert_eq!(contract.get_admin_proposals(1, 10).len(), 1);
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.veto_admin_action(owner_id);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp((1_000 + DEFAULT_TIMELOCK_DELAY_SEC) * second)
+            .build());
+        contract.execute_admin_action(fee_id);
+        assert_eq!((contract.exchange_fee, contract.referral_fee), (10, 2));
+        let statuses: Vec<ProposalStatus> = contract
+            .get_admin_proposals(0, 10)
+            .into_iter()
+            .map(|proposal| proposal.status)
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![ProposalStatus::Executed, ProposalStatus::Vetoed]
+        );
+        assert_eq!(contract.get_owner(), accounts(0).to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "E162: admin proposal is still timelocked")]
+    fn test_admin_timelock_not_passed() {
+        let (mut context, mut contract) = setup_contract();
+        contract.owner_id = accounts(0).into();
+        testing_env!(context.attached_deposit(1).build());
+        let proposal_id = contract.propose_admin_action(AdminAction::SetOwner {
+            owner_id: accounts(2),
+        });
+        contract.execute_admin_action(proposal_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "E164: admin proposal can not be vetoed")]
+    fn test_veto_guardian_removal() {
+        let (mut context, mut contract) = setup_contract();
+        contract.owner_id = accounts(0).into();
+        contract.guardians.insert(&accounts(1).into());
+        testing_env!(context.attached_deposit(1).build());
+        let proposal_id = contract.propose_admin_action(AdminAction::RemoveGuardians {
+            guardians: vec![accounts(1)],
+        });
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.veto_admin_action(proposal_id);
+    }
+
+    #[test]
+    fn test_roles() {
+        let (mut context, mut contract) = setup_contract();
+        contract.owner_id = accounts(0).into();
+        testing_env!(context.attached_deposit(1).build());
+        contract.grant_role(Role::TokenManager, accounts(1));
+        contract.grant_role(Role::PauseManager, accounts(1));
+        contract.grant_role(Role::TokenManager, accounts(2));
+        assert_eq!(
+            contract.get_role_members(Role::TokenManager),
+            vec![accounts(1).to_string(), accounts(2).to_string()]
+        );
+        assert!(contract.has_role(Role::FeeManager, accounts(0)));
+        assert!(!contract.has_role(Role::FeeManager, accounts(1)));
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.extend_whitelisted_tokens(vec![accounts(3)]);
+        assert_eq!(
+            contract.get_whitelisted_tokens(),
+            vec![accounts(3).to_string()]
+        );
+        contract.remove_whitelisted_tokens(vec![accounts(3)]);
+        assert!(contract.get_whitelisted_tokens().is_empty());
+
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.revoke_role(Role::TokenManager, accounts(1));
+        assert_eq!(
+            contract.get_account_roles(accounts(1)),
+            vec![Role::PauseManager]
+        );
+        assert_eq!(contract.get_roles().len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "Requires attached deposit of exactly 1 yoctoNEAR")]
+    fn test_remove_whitelisted_tokens_without_deposit() {
+        let (_, mut contract) = setup_contract();
+        contract.remove_whitelisted_tokens(vec![accounts(3)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "E100: no permission to invoke this")]
+    fn test_guardian_without_role() {
+        let (mut context, mut contract) = setup_contract();
+        contract.guardians.insert(&accounts(1).into());
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(1)
+            .build());
+        contract.change_state(RunningState::Paused);
+    }
+
+    #[test]
+    #[should_panic(expected = "E100: no permission to invoke this")]
+    fn test_guardian_withdraw_only_from_paused() {
+        let (mut context, mut contract) = setup_contract();
+        contract.guardians.insert(&accounts(1).into());
+        contract.state = RunningState::Paused;
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(1)
+            .build());
+        contract.change_state(RunningState::WithdrawOnly);
+    }
+
+    #[test]
+    fn test_withdraw_only() {
+        let (mut context, mut contract) = setup_contract();
+        contract.owner_id = accounts(0).into();
+        let pool_id = setup_pool_with_liquidity(&mut contract);
+        contract.guardians.insert(&accounts(1).into());
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(1)
+            .build());
+        contract.change_state(RunningState::WithdrawOnly);
+
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        let shares = contract.get_account_shares_in_pool(pool_id, accounts(0));
+        contract.remove_liquidity(pool_id, shares, vec![U128(1), U128(1)]);
+        assert_eq!(
+            contract
+                .internal_unwrap_account(&accounts(0).into())
+                .get_balance(&accounts(2).into()),
+            Some(1_000_000)
+        );
+        contract.change_state(RunningState::Running);
+        assert_eq!(contract.state, RunningState::Running);
+    }
+
+    #[test]
+    #[should_panic(expected = "E52: contract only allows withdrawals")]
+    fn test_add_liquidity_withdraw_only() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = setup_pool_with_liquidity(&mut contract);
+        contract.state = RunningState::WithdrawOnly;
+        testing_env!(context.attached_deposit(1).build());
+        contract.add_liquidity(pool_id, vec![U128(1_000), U128(1_000)], None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_ALLOWED")]
+    fn test_guardian_leave_withdraw_only() {
+        let (mut context, mut contract) = setup_contract();
+        contract.guardians.insert(&accounts(1).into());
+        contract
+            .roles
+            .insert(&accounts(1).into(), &vec![Role::PauseManager]);
+        contract.state = RunningState::WithdrawOnly;
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(1)
+            .build());
+        contract.change_state(RunningState::Paused);
+    }
+
+    #[test]
+    fn test_account_storage_reconciliation() {
+        let (mut context, mut contract) = setup_contract();
+        // Account upgraded from a smaller layout, with deposit covering only that one.
+        let account_id: AccountId = accounts(0).into();
+        let mut account = Account::new(&account_id);
+        account.storage_used = INIT_ACCOUNT_STORAGE - 10;
+        account.near_amount = Account::min_storage_usage() - 1;
+        contract.accounts.insert(&account_id, &account.into());
+
+        testing_env!(context.attached_deposit(1).build());
+        contract.register_tokens(vec![]);
+        assert_eq!(
+            contract.internal_unwrap_account(&account_id).storage_used,
+            INIT_ACCOUNT_STORAGE - 10
+        );
+
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.storage_deposit(None, None);
+        assert_eq!(
+            contract.internal_unwrap_account(&account_id).storage_used,
+            INIT_ACCOUNT_STORAGE
+        );
+    }
+
+    #[test]
+    fn test_legacy_account() {
+        let (_, mut contract) = setup_contract();
+        // Account stored by the first version, which didn't set storage_used.
+        let account_id: AccountId = accounts(0).into();
+        let mut tokens = UnorderedMap::new(StorageKey::AccountTokens {
+            account_id: account_id.clone(),
+        });
+        tokens.insert(&accounts(2).to_string(), &100);
+        contract.legacy_accounts.insert(
+            &account_id,
+            &AccountV1 {
+                near_amount: ONE_NEAR,
+                tokens,
+                storage_used: 0,
+            },
+        );
+        let account = contract.internal_unwrap_account(&account_id);
+        assert_eq!(account.storage_used, INIT_ACCOUNT_STORAGE);
+        assert_eq!(
+            contract.storage_balance_of(accounts(0)).unwrap().available,
+            U128(ONE_NEAR - account.storage_usage())
+        );
+
+        contract.internal_deposit(&account_id, &accounts(2).to_string(), 50);
+        assert!(!contract.legacy_accounts.contains_key(&account_id));
+        assert_eq!(
+            contract.get_account_balance(accounts(0).to_string(), accounts(2).to_string()),
+            U128(150)
+        );
+    }
+
+    /// Contract as stored by the first version.
+    #[derive(BorshSerialize, BorshDeserialize)]
+    struct BaselineContract {
+        owner_id: AccountId,
+        accounts: LookupMap<AccountId, BaselineAccount>,
+        pools: Vector<BaselinePool>,
+        exchange_fee: u32,
+        referral_fee: u32,
+        whitelisted_tokens: UnorderedSet<AccountId>,
+        guardians: UnorderedSet<AccountId>,
+        state: RunningState,
+    }
+
+    #[derive(BorshSerialize, BorshDeserialize)]
+    struct BaselineAccount {
+        near_amount: Balance,
+        tokens: UnorderedMap<AccountId, Balance>,
+        storage_used: StorageUsage,
+    }
+
+    #[derive(BorshSerialize, BorshDeserialize)]
+    enum BaselinePool {
+        SimplePool(BaselineSimplePool),
+    }
+
+    #[derive(BorshSerialize, BorshDeserialize)]
+    struct BaselineSimplePool {
+        token_account_ids: Vec<AccountId>,
+        amounts: Vec<Balance>,
+        volumes: Vec<SwapVolume>,
+        total_fee: u32,
+        exchange_fee: u32,
+        referral_fee: u32,
+        shares: UnorderedMap<AccountId, Balance>,
+        shares_total_supply: Balance,
+        first_provider: Option<AccountId>,
+    }
+
+    /// Writes state of the first version with an account of alice and her pool, then migrates it.
+    fn setup_migrated_contract() -> (VMContextBuilder, Contract) {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        let account_id: AccountId = accounts(0).into();
+        let mut tokens = UnorderedMap::new(StorageKey::AccountTokens {
+            account_id: account_id.clone(),
+        });
+        for token in [accounts(2), accounts(3)].iter() {
+            tokens.insert(&token.to_string(), &900_000);
+        }
+        let mut baseline_accounts = LookupMap::new(StorageKey::Account);
+        baseline_accounts.insert(
+            &account_id,
+            &BaselineAccount {
+                near_amount: ONE_NEAR,
+                tokens,
+                storage_used: 0,
+            },
+        );
+        let mut shares = UnorderedMap::new(StorageKey::Shares { pool_id: 0 });
+        shares.insert(&account_id, &INIT_SHARES_SUPPLY);
+        let mut pools = Vector::new(StorageKey::Pools);
+        pools.push(&BaselinePool::SimplePool(BaselineSimplePool {
+            token_account_ids: vec![accounts(2).into(), accounts(3).into()],
+            amounts: vec![100_000, 100_000],
+            volumes: vec![SwapVolume::default(); 2],
+            total_fee: 30,
+            exchange_fee: 0,
+            referral_fee: 0,
+            shares,
+            shares_total_supply: INIT_SHARES_SUPPLY,
+            first_provider: Some(account_id.clone()),
+        }));
+        env::state_write(&BaselineContract {
+            owner_id: account_id,
+            accounts: baseline_accounts,
+            pools,
+            exchange_fee: 5,
+            referral_fee: 0,
+            whitelisted_tokens: UnorderedSet::new(StorageKey::Whitelist),
+            guardians: UnorderedSet::new(StorageKey::Guardian),
+            state: RunningState::Running,
+        });
+        (context, Contract::migrate())
+    }
+
+    #[test]
+    fn test_pool_migration() {
+        let (mut context, mut contract) = setup_migrated_contract();
+        let pool_id = 0;
+        assert_eq!(
+            contract.get_pool(pool_id).amounts,
+            vec![U128(100_000), U128(100_000)]
+        );
+        assert_eq!(
+            contract.get_account_balance(accounts(0).to_string(), accounts(2).to_string()),
+            U128(900_000)
+        );
+        assert_eq!(
+            contract.internal_unwrap_account(&accounts(0).into()).storage_used,
+            INIT_ACCOUNT_STORAGE
+        );
+        assert_eq!(contract.get_timelock_delay(), DEFAULT_TIMELOCK_DELAY_SEC);
+
+        testing_env!(context.attached_deposit(1).build());
+        assert_eq!(
+            contract.migrate_pools(10),
+            PoolMigration {
+                next_index: 1,
+                end_index: 1
+            }
+        );
+        assert!(contract.pools.get(pool_id).unwrap().is_current());
+        assert!(matches!(
+            contract.internal_get_pool(pool_id).unwrap(),
+            Pool::SimplePool(SimplePool {
+                oracle: Some(_),
+                ..
+            })
+        ));
+        assert_eq!(
+            contract.get_pools_by_pair(accounts(2), accounts(3)),
+            vec![pool_id]
+        );
+        assert_eq!(
+            contract.get_pools_by_token(accounts(3), 0, 10),
+            vec![pool_id]
+        );
+        let amount_out = contract.swap(
+            vec![SwapAction {
+                pool_id,
+                token_in: accounts(2).to_string(),
+                amount_in: Some(U128(1_000)),
+                token_out: accounts(3).to_string(),
+                min_amount_out: U128(1),
+            }],
+            None,
+        );
+        assert_eq!(
+            contract.get_account_balance(accounts(0).to_string(), accounts(3).to_string()),
+            U128(900_000 + amount_out.0)
+        );
+        assert!(!contract.legacy_accounts.contains_key(&accounts(0).into()));
+    }
+
+    #[test]
+    fn test_add_liquidity_unmigrated_pool() {
+        let (mut context, mut contract) = setup_migrated_contract();
+        let pool_id = 0;
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.add_liquidity(pool_id, vec![U128(1_000), U128(1_000)], None);
+        assert!(contract.pools.get(pool_id).unwrap().is_current());
+
+        testing_env!(context.attached_deposit(1).build());
+        contract.migrate_pools(10);
+        assert!(matches!(
+            contract.internal_get_pool(pool_id).unwrap(),
+            Pool::SimplePool(SimplePool {
+                oracle: Some(_),
+                ..
+            })
+        ));
+        assert_eq!(
+            contract.get_pools_by_pair(accounts(2), accounts(3)),
+            vec![pool_id]
+        );
+        assert_eq!(
+            contract.get_pools_by_token(accounts(2), 0, 10),
+            vec![pool_id]
+        );
+        assert_eq!(
+            contract.get_pool(pool_id).amounts,
+            vec![U128(101_000), U128(101_000)]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E74: pool is not migrated yet")]
+    fn test_swap_unmigrated_pool() {
+        let (mut context, mut contract) = setup_migrated_contract();
+        testing_env!(context.attached_deposit(1).build());
+        contract.swap(
+            vec![SwapAction {
+                pool_id: 0,
+                token_in: accounts(2).to_string(),
+                amount_in: Some(U128(1_000)),
+                token_out: accounts(3).to_string(),
+                min_amount_out: U128(1),
+            }],
+            None,
+        );
+    }
+
+    #[test]
+    fn test_upgrade() {
+        let (mut context, mut contract) = setup_contract();
+        let second = 1_000_000_000;
+        contract.owner_id = accounts(0).into();
+        contract.upgrade_delay_sec = 100;
+        context.context.input = vec![0, 97, 115, 109];
+        testing_env!(context.block_timestamp(1_000 * second).build());
+        let staged_upgrade = contract.stage_code();
+        let code_hash: [u8; 32] = env::sha256(&[0, 97, 115, 109]).try_into().unwrap();
+        assert_eq!(
+            contract.get_staged_upgrade(),
+            Some(StagedUpgrade {
+                code_hash: code_hash.into(),
+                deployable_at: 1_100
+            })
+        );
+        assert_eq!(contract.get_staged_upgrade(), Some(staged_upgrade.clone()));
+
+        testing_env!(context.block_timestamp(1_100 * second).build());
+        contract.deploy_staged_code();
+        assert!(contract.staged_code.is_some());
+
+        // Failed migration keeps the code staged.
+        testing_env!(
+            context.build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.callback_deploy_staged_code(code_hash.into());
+        assert_eq!(contract.get_staged_upgrade(), Some(staged_upgrade));
+
+        testing_env!(
+            context.build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        contract.callback_deploy_staged_code(code_hash.into());
+        assert!(contract.get_staged_upgrade().is_none());
+        assert!(!contract.staged_code.is_some());
+    }
+
+    #[test]
+    fn test_guardian_remove_staged_code() {
+        let (mut context, mut contract) = setup_contract();
+        contract.owner_id = accounts(0).into();
+        contract.guardians.insert(&accounts(1).into());
+        assert_eq!(contract.get_upgrade_delay(), DEFAULT_TIMELOCK_DELAY_SEC);
+        context.context.input = vec![0, 97, 115, 109];
+        testing_env!(context.build());
+        contract.stage_code();
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.remove_staged_code();
+        assert!(contract.get_staged_upgrade().is_none());
+        assert!(!contract.staged_code.is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "E171: staged code can not be deployed yet")]
+    fn test_upgrade_before_delay() {
+        let (mut context, mut contract) = setup_contract();
+        contract.owner_id = accounts(0).into();
+        contract.upgrade_delay_sec = 100;
+        context.context.input = vec![0, 97, 115, 109];
+        testing_env!(context.build());
+        contract.stage_code();
+        contract.deploy_staged_code();
+    }
+
+    fn setup_pool_with_liquidity(contract: &mut Contract) -> u64 {
+        contract.storage_deposit(None, None);
+        for token in [accounts(2), accounts(3)].iter() {
+            contract.internal_transfer_from_user(
+                &accounts(0).to_string(),
+                &token.to_string(),
+                1_000_000,
+            );
+        }
+        let pool_id = contract.add_simple_pool(vec![accounts(2), accounts(3)], 30);
+        contract.add_liquidity(pool_id, vec![U128(100_000), U128(100_000)], None);
+        pool_id
+    }
+
+    #[test]
+    fn test_pool_state() {
+        let (mut context, mut contract) = setup_contract();
+        contract.owner_id = accounts(0).into();
+        let pool_id = setup_pool_with_liquidity(&mut contract);
+        contract.guardians.insert(&accounts(1).into());
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(1)
+            .build());
+        contract.set_pool_state(pool_id, PoolState::SwapsPaused);
+        assert_eq!(contract.get_pool(pool_id).state, PoolState::SwapsPaused);
+
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.add_liquidity(pool_id, vec![U128(1_000), U128(1_000)], None);
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.set_pool_state(pool_id, PoolState::WithdrawOnly);
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.set_pool_state(pool_id, PoolState::Deprecated);
+        let shares = contract.get_account_shares_in_pool(pool_id, accounts(0));
+        contract.remove_liquidity(pool_id, shares, vec![U128(1), U128(1)]);
+        assert!(contract.get_pair_fee_tiers(accounts(2), accounts(3))[2]
+            .pool_id
+            .is_none());
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        let new_pool_id = contract.add_simple_pool(vec![accounts(2), accounts(3)], 30);
+        assert_eq!(contract.get_pool_state(new_pool_id), PoolState::Running);
+        assert_eq!(
+            contract.get_pools_by_pair(accounts(2), accounts(3)),
+            vec![new_pool_id]
+        );
+        assert_eq!(
+            contract.get_pools_by_token(accounts(2), 0, 10),
+            vec![new_pool_id]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E100: no permission to invoke this")]
+    fn test_guardian_deprecate_pool() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = setup_pool_with_liquidity(&mut contract);
+        contract.guardians.insert(&accounts(1).into());
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(1)
+            .build());
+        contract.set_pool_state(pool_id, PoolState::Deprecated);
+    }
+
+    #[test]
+    #[should_panic(expected = "E78: pool is deprecated")]
+    fn test_reactivate_deprecated_pool() {
+        let (mut context, mut contract) = setup_contract();
+        contract.owner_id = accounts(0).into();
+        let pool_id = setup_pool_with_liquidity(&mut contract);
+        testing_env!(context.attached_deposit(1).build());
+        contract.set_pool_state(pool_id, PoolState::Deprecated);
+        contract.set_pool_state(pool_id, PoolState::Running);
+    }
+
+    #[test]
+    #[should_panic(expected = "E75: pool is not running")]
+    fn test_swap_paused_pool() {
+        let (mut context, mut contract) = setup_contract();
+        contract.owner_id = accounts(0).into();
+        let pool_id = setup_pool_with_liquidity(&mut contract);
+        testing_env!(context.attached_deposit(1).build());
+        contract.set_pool_state(pool_id, PoolState::SwapsPaused);
+        contract.swap(
+            vec![SwapAction {
+                pool_id,
+                token_in: accounts(2).to_string(),
+                amount_in: Some(U128(1_000)),
+                token_out: accounts(3).to_string(),
+                min_amount_out: U128(1),
+            }],
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E100: no permission to invoke this")]
+    fn test_guardian_resume_pool() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = setup_pool_with_liquidity(&mut contract);
+        contract.guardians.insert(&accounts(1).into());
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(1)
+            .build());
+        contract.set_pool_state(pool_id, PoolState::WithdrawOnly);
+        contract.set_pool_state(pool_id, PoolState::SwapsPaused);
+    }
+
+    #[test]
+    #[should_panic(expected = "E77: amount overflows normalized precision")]
+    fn test_stable_pool_amount_overflow() {
+        let (mut context, mut contract) = setup_contract();
+        contract.owner_id = accounts(0).into();
+        contract.storage_deposit(None, None);
+        let amount = 1_000_000_000_000_000_000_000;
+        for token in [accounts(2), accounts(3)].iter() {
+            contract.internal_transfer_from_user(
+                &accounts(0).to_string(),
+                &token.to_string(),
+                amount,
+            );
+        }
+        let pool_id =
+            contract.add_stable_swap_pool(vec![accounts(2), accounts(3)], vec![6, 24], 25, 100);
+        testing_env!(context.attached_deposit(1).build());
+        contract.add_stable_liquidity(pool_id, vec![U128(amount), U128(amount)], U128(1));
+    }
+
+    #[test]
+    fn test_fee_tiers() {
+        let (mut context, mut contract) = setup_contract();
+        contract.owner_id = accounts(0).into();
+        let pool_id = contract.add_simple_pool(vec![accounts(2), accounts(3)], 30);
+        testing_env!(context.attached_deposit(1).build());
+        contract.add_fee_tier(50);
+        contract.remove_fee_tier(30);
+        assert_eq!(contract.get_fee_tiers(), vec![1, 5, 50, 100]);
+        let fees: Vec<(u32, Option<u64>)> = contract
+            .get_pair_fee_tiers(accounts(3), accounts(2))
+            .into_iter()
+            .map(|tier| (tier.fee, tier.pool_id))
+            .collect();
+        assert_eq!(
+            fees,
+            vec![(1, None), (5, None), (30, Some(pool_id)), (50, None), (100, None)]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E88: fee is not an allowed fee tier")]
+    fn test_fee_tier_not_allowed() {
+        let (_, mut contract) = setup_contract();
+        contract.add_simple_pool(vec![accounts(2), accounts(3)], 25);
+    }
+
+    #[test]
+    #[should_panic(expected = "E87: pool with same tokens and fee already exists")]
+    fn test_pool_existed_reversed_tokens() {
+        let (_, mut contract) = setup_contract();
+        contract.add_simple_pool(vec![accounts(2), accounts(3)], 30);
+        contract.add_simple_pool(vec![accounts(3), accounts(2)], 30);
+    }
+
+    #[test]
+    #[should_panic(expected = "E87: pool with same tokens and fee already exists")]
+    fn test_weighted_pool_existed() {
+        let (_, mut contract) = setup_contract();
+        contract.owner_id = accounts(0).into();
+        // Pools of other kinds don't count as duplicates.
+        contract.add_simple_pool(vec![accounts(2), accounts(3)], 30);
+        contract.add_weighted_pool(vec![accounts(2), accounts(3)], vec![8000, 2000], 30);
+        contract.add_weighted_pool(vec![accounts(3), accounts(2)], vec![5000, 5000], 30);
+    }
+
+    #[test]
+    #[should_panic(expected = "E100: no permission to invoke this")]
+    fn test_weighted_pool_without_role() {
+        let (_, mut contract) = setup_contract();
+        contract.add_weighted_pool(vec![accounts(2), accounts(3)], vec![8000, 2000], 30);
+    }
+
+    #[test]
+    fn test_mft_transfer() {
+        let (mut context, mut contract) = setup_contract();
+        contract.storage_deposit(None, None);
+        for token in [accounts(2), accounts(3)].iter() {
+            contract.internal_transfer_from_user(
+                &accounts(0).to_string(),
+                &token.to_string(),
+                1_000_000,
+            );
+        }
+        let pool_id = contract.add_simple_pool(vec![accounts(2), accounts(3)], 30);
+        contract.add_liquidity(pool_id, vec![U128(100_000), U128(100_000)], None);
+        let token_id = format!(":{}", pool_id);
+        let total_shares = contract.mft_total_supply(token_id.clone()).0;
+        assert_eq!(
+            contract.mft_balance_of(token_id.clone(), accounts(0)).0,
+            total_shares
+        );
+        assert_eq!(contract.mft_metadata(token_id.clone()).decimals, 24);
+
+        contract.mft_register(token_id.clone(), accounts(1));
+        testing_env!(context.attached_deposit(1).build());
+        contract.mft_transfer(
+            token_id.clone(),
+            accounts(1),
+            U128(1_000),
+            Some("memo".to_string()),
+        );
+        assert!(get_logs().last().unwrap().ends_with(
+            "\"event\":\"share_transfer\",\"data\":{\"pool_id\":0,\"sender_id\":\"alice\",\
+            \"receiver_id\":\"bob\",\"amount\":\"1000\",\"memo\":\"memo\"}}"
+        ));
+        assert_eq!(contract.mft_balance_of(token_id.clone(), accounts(1)).0, 1_000);
+        assert_eq!(
+            contract.mft_balance_of(token_id, accounts(0)).0,
+            total_shares - 1_000
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "AMOUNT_MUST_BE_GREATER_THAN_0")]
     fn test_deposit_token_with_zero_amount() {
-        use near_sdk::env;
         let token_id = accounts(3);
         let (_, mut contract) = setup_contract();
         let amount: Balance = 0;
@@ -0,0 +1,365 @@
+//! Limit orders resting on top of the pools.
+//!
+//! An order escrows `amount_in` of `token_in` from the owner's deposits and is filled by swapping it
+//! through its pool once that returns at least `min_amount_out` of `token_out`.
+//! Open orders of a pool are kept sorted by pair and limit price, the most fillable first.
+//! Orders of a simple pool are checked after each swap in it in this order, up to
+//! `MAX_AUTO_FILL_CHECKS` of them, skipping the rest of a pair once one of its orders is not fillable.
+//! Other orders can be filled by anyone with `fill_limit_orders`. Pools of other kinds are not
+//! auto filled, as quoting a large order there may fail and that must not block swaps in the pool.
+//! Received tokens stay in the order until its owner claims them.
+//! Each order bonds `LIMIT_ORDER_BOND` of the owner's storage balance until it is cancelled or
+//! claimed, and an account can have up to `MAX_ORDERS_PER_ACCOUNT` orders, so filling up
+//! the order book of a pool is costly.
+
+use std::cmp::Ordering;
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::utils::U256;
+use crate::*;
+
+/// Max number of open orders per pool.
+pub const MAX_OPEN_ORDERS_PER_POOL: usize = 100;
+/// Max number of open orders checked for fill after a swap.
+pub const MAX_AUTO_FILL_CHECKS: usize = 10;
+/// Max number of orders of an account, open ones and filled ones waiting for claim.
+pub const MAX_ORDERS_PER_ACCOUNT: u32 = 10;
+/// Storage balance (in yoctoNEAR) bonded by each order, 0.1 NEAR.
+pub const LIMIT_ORDER_BOND: Balance = 100_000_000_000_000_000_000_000;
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct LimitOrder {
+    pub owner_id: AccountId,
+    pub pool_id: u64,
+    pub token_in: AccountId,
+    pub token_out: AccountId,
+    /// Escrowed amount of `token_in`.
+    pub amount_in: Balance,
+    /// Min amount of `token_out` to receive, defines the limit price.
+    pub min_amount_out: Balance,
+    /// Received amount of `token_out`, set once the order is filled.
+    pub amount_out: Option<Balance>,
+}
+
+impl LimitOrder {
+    /// Orders by pair, then by limit price `min_amount_out / amount_in`, lower first.
+    fn cmp_fill_priority(&self, other: &Self) -> Ordering {
+        (&self.token_in, &self.token_out)
+            .cmp(&(&other.token_in, &other.token_out))
+            .then_with(|| {
+                (U256::from(self.min_amount_out) * U256::from(other.amount_in))
+                    .cmp(&(U256::from(other.min_amount_out) * U256::from(self.amount_in)))
+            })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct LimitOrderInfo {
+    pub order_id: u64,
+    pub owner_id: AccountId,
+    pub pool_id: u64,
+    pub token_in: AccountId,
+    pub token_out: AccountId,
+    pub amount_in: U128,
+    pub min_amount_out: U128,
+    pub amount_out: Option<U128>,
+}
+
+impl LimitOrderInfo {
+    fn new(order_id: u64, order: LimitOrder) -> Self {
+        Self {
+            order_id,
+            owner_id: order.owner_id,
+            pool_id: order.pool_id,
+            token_in: order.token_in,
+            token_out: order.token_out,
+            amount_in: U128(order.amount_in),
+            min_amount_out: U128(order.min_amount_out),
+            amount_out: order.amount_out.map(U128),
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Places order to swap `amount_in` of deposited `token_in` for at least `min_amount_out` of `token_out`
+    /// in given pool, which has to be running. Attached deposit should cover the storage of the order,
+    /// the rest is refunded. `LIMIT_ORDER_BOND` is not taken from the attached deposit but bonded out of
+    /// the owner's storage balance until the order is settled. Returns id of the order.
+    #[payable]
+    pub fn place_limit_order(
+        &mut self,
+        pool_id: u64,
+        token_in: ValidAccountId,
+        amount_in: U128,
+        token_out: ValidAccountId,
+        min_amount_out: U128,
+    ) -> u64 {
+        self.assert_contract_running();
+        self.assert_pool_migrated(pool_id);
+        self.assert_pool_running(pool_id);
+        assert!(
+            amount_in.0 > 0 && min_amount_out.0 > 0,
+            "{}",
+            ERR31_ZERO_AMOUNT
+        );
+        assert_ne!(token_in, token_out, "{}", ERR71_SWAP_DUP_TOKENS);
+        let prev_storage = env::storage_usage();
+        let sender_id = env::predecessor_account_id();
+        let pool = self.internal_get_pool(pool_id).expect(ERR85_NO_POOL);
+        for token_id in [&token_in, &token_out] {
+            assert!(
+                pool.tokens().contains(token_id.as_ref()),
+                "{}",
+                ERR63_MISSING_TOKEN
+            );
+        }
+        let mut order_ids = self.pool_limit_orders.get(&pool_id).unwrap_or_default();
+        assert!(
+            order_ids.len() < MAX_OPEN_ORDERS_PER_POOL,
+            "{}",
+            ERR114_TOO_MANY_ORDERS
+        );
+        let num_orders = self.limit_order_counts.get(&sender_id).unwrap_or_default();
+        assert!(
+            num_orders < MAX_ORDERS_PER_ACCOUNT,
+            "{}",
+            ERR115_TOO_MANY_ACCOUNT_ORDERS
+        );
+        self.limit_order_counts
+            .insert(&sender_id, &(num_orders + 1));
+
+        let mut account = self.internal_unwrap_account(&sender_id);
+        assert!(
+            account.storage_available() >= LIMIT_ORDER_BOND,
+            "{}",
+            ERR116_INSUFFICIENT_ORDER_BOND
+        );
+        account.near_amount -= LIMIT_ORDER_BOND;
+        account.withdraw(token_in.as_ref(), amount_in.0);
+        self.internal_save_account(&sender_id, account);
+
+        let order_id = self.next_limit_order_id;
+        self.next_limit_order_id += 1;
+        let order = LimitOrder {
+            owner_id: sender_id,
+            pool_id,
+            token_in: token_in.into(),
+            token_out: token_out.into(),
+            amount_in: amount_in.0,
+            min_amount_out: min_amount_out.0,
+            amount_out: None,
+        };
+        // Orders with the same pair and limit price are kept in order of placement.
+        let index = order_ids.partition_point(|id| {
+            self.limit_orders.get(id).unwrap().cmp_fill_priority(&order) != Ordering::Greater
+        });
+        order_ids.insert(index, order_id);
+        self.limit_orders.insert(&order_id, &order);
+        self.pool_limit_orders.insert(&pool_id, &order_ids);
+        self.internal_check_storage(prev_storage);
+        order_id
+    }
+
+    /// Cancels open order and returns escrowed tokens to the owner's deposits.
+    #[payable]
+    pub fn cancel_limit_order(&mut self, order_id: u64) {
+        assert_one_yocto();
+        let prev_storage = env::storage_usage();
+        let order = self.internal_remove_limit_order(order_id);
+        assert!(
+            order.amount_out.is_none(),
+            "{}",
+            ERR113_ORDER_ALREADY_FILLED
+        );
+        self.internal_remove_open_order(order.pool_id, order_id);
+        self.internal_settle_limit_order(
+            prev_storage,
+            &order.owner_id,
+            &order.token_in,
+            order.amount_in,
+        );
+    }
+
+    /// Claims tokens received by filled order into the owner's deposits.
+    #[payable]
+    pub fn claim_limit_order(&mut self, order_id: u64) {
+        assert_one_yocto();
+        let prev_storage = env::storage_usage();
+        let order = self.internal_remove_limit_order(order_id);
+        let amount_out = order.amount_out.expect(ERR112_ORDER_NOT_FILLED);
+        self.internal_settle_limit_order(
+            prev_storage,
+            &order.owner_id,
+            &order.token_out,
+            amount_out,
+        );
+    }
+
+    /// Fills given open orders of given pool that the current pool price satisfies.
+    /// Can be called by anyone. Returns ids of filled orders.
+    pub fn fill_limit_orders(&mut self, pool_id: u64, order_ids: Vec<u64>) -> Vec<u64> {
+        self.assert_contract_running();
+        self.assert_no_flash_loan(pool_id);
+        self.assert_pool_migrated(pool_id);
+        self.assert_pool_running(pool_id);
+        let mut pool = self.internal_get_pool(pool_id).expect(ERR85_NO_POOL);
+        // Fills mint admin fee shares to the exchange.
+        self.internal_checkpoint_farmers(pool_id, &pool, &[&env::current_account_id()]);
+        let filled = self.internal_fill_limit_orders(pool_id, &mut pool, &order_ids);
+        self.internal_save_pool(pool_id, pool);
+        filled
+    }
+
+    pub fn get_limit_order(&self, order_id: u64) -> Option<LimitOrderInfo> {
+        self.limit_orders
+            .get(&order_id)
+            .map(|order| LimitOrderInfo::new(order_id, order))
+    }
+
+    /// Returns open orders of given pool, by pair and from the lowest limit price.
+    pub fn get_pool_limit_orders(&self, pool_id: u64) -> Vec<LimitOrderInfo> {
+        self.pool_limit_orders
+            .get(&pool_id)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|order_id| {
+                LimitOrderInfo::new(order_id, self.limit_orders.get(&order_id).unwrap())
+            })
+            .collect()
+    }
+}
+
+impl Contract {
+    /// Fills those of given open orders that the current price of given pool satisfies.
+    /// Ids of orders that are not open in this pool are skipped. Returns ids of filled orders.
+    pub(crate) fn internal_fill_limit_orders(
+        &mut self,
+        pool_id: u64,
+        pool: &mut Pool,
+        order_ids: &[u64],
+    ) -> Vec<u64> {
+        let mut open_ids = self.pool_limit_orders.get(&pool_id).unwrap_or_default();
+        let mut filled = vec![];
+        for order_id in order_ids {
+            if !open_ids.contains(order_id) {
+                continue;
+            }
+            let mut order = self.limit_orders.get(order_id).unwrap();
+            let amount_out = pool.get_return(&order.token_in, order.amount_in, &order.token_out);
+            if amount_out < order.min_amount_out {
+                continue;
+            }
+            pool.swap(
+                &order.token_in,
+                order.amount_in,
+                &order.token_out,
+                order.min_amount_out,
+                AdminFees {
+                    exchange_fee: self.exchange_fee,
+                    exchange_id: env::current_account_id(),
+                    referral_fee: 0,
+                    referral_id: None,
+                },
+            );
+            Event::Swap {
+                account_id: &order.owner_id,
+                pool_id,
+                token_in: &order.token_in,
+                amount_in: U128(order.amount_in),
+                token_out: &order.token_out,
+                amount_out: U128(amount_out),
+            }
+            .emit();
+            order.amount_out = Some(amount_out);
+            self.limit_orders.insert(order_id, &order);
+            filled.push(*order_id);
+        }
+        if !filled.is_empty() {
+            open_ids.retain(|order_id| !filled.contains(order_id));
+            self.pool_limit_orders.insert(&pool_id, &open_ids);
+        }
+        filled
+    }
+
+    /// Fills open orders of given simple pool that became fillable after a swap in it.
+    /// Checks orders from the lowest limit price of each pair, until one of the pair is not fillable.
+    pub(crate) fn internal_auto_fill_limit_orders(&mut self, pool_id: u64, pool: &mut Pool) {
+        if !matches!(pool, Pool::SimplePool(_)) {
+            return;
+        }
+        let open_ids = self.pool_limit_orders.get(&pool_id).unwrap_or_default();
+        let mut filled = vec![];
+        let mut skipped_pair: Option<(AccountId, AccountId)> = None;
+        for order_id in open_ids.into_iter().take(MAX_AUTO_FILL_CHECKS) {
+            let order = self.limit_orders.get(&order_id).unwrap();
+            let pair = (order.token_in, order.token_out);
+            if skipped_pair.as_ref() == Some(&pair) {
+                continue;
+            }
+            if pool.get_return(&pair.0, order.amount_in, &pair.1) >= order.min_amount_out {
+                filled.push(order_id);
+            } else {
+                skipped_pair = Some(pair);
+            }
+        }
+        if !filled.is_empty() {
+            self.internal_fill_limit_orders(pool_id, pool, &filled);
+        }
+    }
+
+    /// Removes order of the predecessor from storage.
+    fn internal_remove_limit_order(&mut self, order_id: u64) -> LimitOrder {
+        let order = self
+            .limit_orders
+            .get(&order_id)
+            .expect(ERR110_NO_LIMIT_ORDER);
+        assert_eq!(
+            order.owner_id,
+            env::predecessor_account_id(),
+            "{}",
+            ERR111_NOT_ORDER_OWNER
+        );
+        self.limit_orders.remove(&order_id);
+        let num_orders = self
+            .limit_order_counts
+            .get(&order.owner_id)
+            .unwrap_or_default();
+        if num_orders > 1 {
+            self.limit_order_counts
+                .insert(&order.owner_id, &(num_orders - 1));
+        } else {
+            self.limit_order_counts.remove(&order.owner_id);
+        }
+        order
+    }
+
+    fn internal_remove_open_order(&mut self, pool_id: u64, order_id: u64) {
+        let mut open_ids = self.pool_limit_orders.get(&pool_id).unwrap_or_default();
+        open_ids.retain(|id| *id != order_id);
+        self.pool_limit_orders.insert(&pool_id, &open_ids);
+    }
+
+    /// Deposits tokens released by an order to its owner.
+    /// Bond and freed up storage balance of the order are returned to near_balance.
+    fn internal_settle_limit_order(
+        &mut self,
+        prev_storage: StorageUsage,
+        owner_id: &AccountId,
+        token_id: &AccountId,
+        amount: Balance,
+    ) {
+        let mut account = self.internal_unwrap_account(owner_id);
+        account.deposit(token_id, amount);
+        account.near_amount += LIMIT_ORDER_BOND;
+        if prev_storage > env::storage_usage() {
+            account.near_amount +=
+                (prev_storage - env::storage_usage()) as Balance * env::storage_byte_cost();
+        }
+        self.internal_save_account(owner_id, account);
+    }
+}
@@ -0,0 +1,68 @@
+//! Migration of pools stored in layouts of previous versions.
+//!
+//! `migrate` only rewrites the contract state, pools are converted afterwards by `migrate_pools`
+//! in batches small enough for the gas limit, walking the pools that existed at the upgrade.
+//! Until converted, a pool can't be swapped in. Pools created since are stored as current.
+//! Liquidity changes store pools of old layouts as current too, so every pool up to the upgrade
+//! is converted and added to the pool index, which older versions didn't have.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::roles::Role;
+use crate::*;
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct PoolMigration {
+    /// Id of the next pool to convert.
+    pub next_index: u64,
+    /// Number of pools at the upgrade, the ones from `next_index` up to it may be in old layouts.
+    pub end_index: u64,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Converts up to `limit` pools to the current version. Only can be called by upgraders.
+    /// Returns progress of the migration.
+    #[payable]
+    pub fn migrate_pools(&mut self, limit: u64) -> PoolMigration {
+        assert_one_yocto();
+        self.assert_role(Role::Upgrader);
+        let from_index = self.pool_migration.next_index;
+        let to_index = std::cmp::min(from_index + limit, self.pool_migration.end_index);
+        for pool_id in from_index..to_index {
+            let pool = self.pools.get(pool_id).unwrap().migrate(pool_id);
+            self.internal_index_pool(pool_id, &pool);
+            self.pools.replace(pool_id, &pool.into());
+        }
+        self.pool_migration.next_index = to_index;
+        Event::PoolsMigrated {
+            from_index,
+            to_index,
+        }
+        .emit();
+        self.pool_migration.clone()
+    }
+
+    /// Returns progress of the migration, it's done once `next_index` reached `end_index`.
+    pub fn get_pool_migration(&self) -> PoolMigration {
+        self.pool_migration.clone()
+    }
+}
+
+impl Contract {
+    pub(crate) fn is_pool_migrated(&self, pool_id: u64) -> bool {
+        pool_id < self.pool_migration.next_index || pool_id >= self.pool_migration.end_index
+    }
+
+    /// Panics if given pool may still be in an old layout.
+    pub(crate) fn assert_pool_migrated(&self, pool_id: u64) {
+        assert!(
+            self.is_pool_migrated(pool_id),
+            "{}",
+            ERR74_POOL_NOT_MIGRATED
+        );
+    }
+}
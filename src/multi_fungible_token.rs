@@ -0,0 +1,201 @@
+//! Multi fungible token interface over LP shares of the pools.
+//! Shares of pool with id N are addressed by token id ":N".
+
+use near_contract_standards::fungible_token::metadata::FungibleTokenMetadata;
+use near_sdk::{ext_contract, PromiseOrValue};
+
+use crate::utils::{ext_self, GAS_FOR_FT_TRANSFER_CALL, GAS_FOR_RESOLVE_TRANSFER};
+use crate::*;
+
+pub const MFT_METADATA_SPEC: &str = "mft-1.0.0";
+
+#[ext_contract(ext_mft_receiver)]
+pub trait MFTTokenReceiver {
+    fn mft_on_transfer(
+        &mut self,
+        token_id: String,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128>;
+}
+
+/// Parses pool id out of token id in ":N" format.
+fn parse_pool_id(token_id: &str) -> u64 {
+    token_id
+        .strip_prefix(':')
+        .and_then(|pool_id| pool_id.parse().ok())
+        .expect(ERR102_INVALID_TOKEN_ID)
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Registers given account for LP shares of given pool.
+    /// Attached deposit should cover the storage, the rest is refunded.
+    #[payable]
+    pub fn mft_register(&mut self, token_id: String, account_id: ValidAccountId) {
+        let prev_storage = env::storage_usage();
+        let pool_id = parse_pool_id(&token_id);
+        let mut pool = self.internal_get_pool(pool_id).expect(ERR85_NO_POOL);
+        pool.share_register(account_id.as_ref());
+        self.internal_save_pool(pool_id, pool);
+        self.internal_check_storage(prev_storage);
+    }
+
+    /// Transfers LP shares of given pool to registered receiver.
+    #[payable]
+    pub fn mft_transfer(
+        &mut self,
+        token_id: String,
+        receiver_id: ValidAccountId,
+        amount: U128,
+        memo: Option<String>,
+    ) {
+        assert_one_yocto();
+        self.assert_contract_running();
+        self.internal_mft_transfer(
+            &token_id,
+            &env::predecessor_account_id(),
+            receiver_id.as_ref(),
+            amount.0,
+            memo,
+        );
+    }
+
+    /// Transfers LP shares and calls `mft_on_transfer` on the receiver.
+    /// Receiver returns amount of unused shares, which are refunded back in `mft_resolve_transfer`.
+    #[payable]
+    pub fn mft_transfer_call(
+        &mut self,
+        token_id: String,
+        receiver_id: ValidAccountId,
+        amount: U128,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        assert_one_yocto();
+        self.assert_contract_running();
+        let sender_id = env::predecessor_account_id();
+        self.internal_mft_transfer(&token_id, &sender_id, receiver_id.as_ref(), amount.0, memo);
+        ext_mft_receiver::mft_on_transfer(
+            token_id.clone(),
+            sender_id.clone(),
+            amount,
+            msg,
+            receiver_id.as_ref(),
+            0,
+            env::prepaid_gas() - GAS_FOR_FT_TRANSFER_CALL,
+        )
+        .then(ext_self::mft_resolve_transfer(
+            token_id,
+            sender_id,
+            receiver_id.into(),
+            amount,
+            &env::current_account_id(),
+            0,
+            GAS_FOR_RESOLVE_TRANSFER,
+        ))
+        .into()
+    }
+
+    /// Returns unused shares from receiver back to sender, as much as receiver still holds.
+    /// Returns amount of shares that were actually used by receiver.
+    #[private]
+    pub fn mft_resolve_transfer(
+        &mut self,
+        token_id: String,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> U128 {
+        let unused_amount = match env::promise_result(0) {
+            PromiseResult::NotReady => unreachable!(),
+            PromiseResult::Successful(value) => {
+                if let Ok(unused_amount) = near_sdk::serde_json::from_slice::<U128>(&value) {
+                    std::cmp::min(amount.0, unused_amount.0)
+                } else {
+                    amount.0
+                }
+            }
+            PromiseResult::Failed => amount.0,
+        };
+        if unused_amount > 0 {
+            let pool_id = parse_pool_id(&token_id);
+            let mut pool = self.internal_get_pool(pool_id).expect(ERR85_NO_POOL);
+            let receiver_balance = pool.share_balances(&receiver_id);
+            if receiver_balance > 0 {
+                let refund_amount = std::cmp::min(receiver_balance, unused_amount);
+                self.internal_checkpoint_farmers(pool_id, &pool, &[&receiver_id, &sender_id]);
+                pool.share_transfer(&receiver_id, &sender_id, refund_amount);
+                self.internal_save_pool(pool_id, pool);
+                Event::ShareTransfer {
+                    pool_id,
+                    sender_id: &receiver_id,
+                    receiver_id: &sender_id,
+                    amount: U128(refund_amount),
+                    memo: None,
+                }
+                .emit();
+                return U128(amount.0 - refund_amount);
+            }
+        }
+        U128(amount.0)
+    }
+
+    /// Returns LP shares balance of given account in given pool.
+    pub fn mft_balance_of(&self, token_id: String, account_id: ValidAccountId) -> U128 {
+        self.internal_get_pool(parse_pool_id(&token_id))
+            .expect(ERR85_NO_POOL)
+            .share_balances(account_id.as_ref())
+            .into()
+    }
+
+    /// Returns total supply of LP shares of given pool.
+    pub fn mft_total_supply(&self, token_id: String) -> U128 {
+        self.internal_get_pool(parse_pool_id(&token_id))
+            .expect(ERR85_NO_POOL)
+            .share_total_balance()
+            .into()
+    }
+
+    pub fn mft_metadata(&self, token_id: String) -> FungibleTokenMetadata {
+        let pool_id = parse_pool_id(&token_id);
+        let pool = self.internal_get_pool(pool_id).expect(ERR85_NO_POOL);
+        FungibleTokenMetadata {
+            spec: MFT_METADATA_SPEC.to_string(),
+            name: format!("forbitswap-pool-{}", pool_id),
+            symbol: format!("FORBIT-POOL-{}", pool_id),
+            icon: None,
+            reference: None,
+            reference_hash: None,
+            decimals: pool.get_share_decimal(),
+        }
+    }
+}
+
+impl Contract {
+    fn internal_mft_transfer(
+        &mut self,
+        token_id: &str,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        amount: Balance,
+        memo: Option<String>,
+    ) {
+        assert_ne!(sender_id, receiver_id, "{}", ERR33_TRANSFER_TO_SELF);
+        assert!(amount > 0, "{}", ERR31_ZERO_AMOUNT);
+        let pool_id = parse_pool_id(token_id);
+        let mut pool = self.internal_get_pool(pool_id).expect(ERR85_NO_POOL);
+        self.internal_checkpoint_farmers(pool_id, &pool, &[sender_id, receiver_id]);
+        pool.share_transfer(sender_id, receiver_id, amount);
+        self.internal_save_pool(pool_id, pool);
+        Event::ShareTransfer {
+            pool_id,
+            sender_id,
+            receiver_id,
+            amount: U128(amount),
+            memo: memo.as_deref(),
+        }
+        .emit();
+    }
+}
@@ -0,0 +1,142 @@
+//! Time weighted average price oracle.
+//! Accumulates price of every ordered pair of pool tokens over time, Uniswap V2 style,
+//! and keeps a ring buffer of periodic snapshots of these accumulators to look back at.
+//! Only simple pools of two tokens keep one, so the accumulators read and written on every swap
+//! stay at two and the snapshots at a couple of KB per pool.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::Vector;
+use near_sdk::{env, Balance, Timestamp};
+
+use crate::errors::*;
+use crate::utils::U256;
+use crate::StorageKey;
+
+/// Precision of prices returned by the oracle.
+pub const PRICE_PRECISION: u128 = 1_000_000_000_000_000_000;
+/// Number of snapshots kept per pool.
+pub const OBSERVATION_CAPACITY: u64 = 24;
+/// Min time (in nanoseconds) between two snapshots, bounds history to about 4 hours.
+pub const OBSERVATION_PERIOD: Timestamp = 600 * 1_000_000_000;
+
+/// Price accumulators at given moment.
+/// Accumulators wrap around on overflow, only differences between them are meaningful.
+#[derive(BorshSerialize, BorshDeserialize, Clone)]
+pub struct Observation {
+    pub timestamp: Timestamp,
+    /// Sum of `price * elapsed_ns` for each ordered pair of tokens, see `pair_index`.
+    pub price_cumulatives: Vec<U256>,
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct PriceOracle {
+    /// Accumulators as of the last pool update.
+    last: Observation,
+    /// Ring buffer of snapshots, preallocated at creation so storage is paid by the pool creator.
+    observations: Vector<Observation>,
+    /// Index of the most recent snapshot in `observations`.
+    observation_index: u64,
+}
+
+impl PriceOracle {
+    pub fn new(pool_id: u32, num_tokens: usize) -> Self {
+        let empty = Observation {
+            timestamp: 0,
+            price_cumulatives: vec![U256::zero(); num_tokens * (num_tokens - 1)],
+        };
+        let mut observations = Vector::new(StorageKey::Observations { pool_id });
+        for _ in 0..OBSERVATION_CAPACITY {
+            observations.push(&empty);
+        }
+        Self {
+            last: empty,
+            observations,
+            observation_index: 0,
+        }
+    }
+
+    /// Accumulates prices given by `amounts` since the last update up to now.
+    /// Must be called before pool balances change.
+    pub fn update(&mut self, amounts: &[Balance]) {
+        let now = env::block_timestamp();
+        if now == self.last.timestamp {
+            return;
+        }
+        if self.last.timestamp != 0 && amounts.iter().all(|amount| *amount > 0) {
+            self.last.price_cumulatives = self.cumulatives_at(amounts, now);
+        }
+        self.last.timestamp = now;
+
+        let latest = self
+            .observations
+            .get(self.observation_index)
+            .expect("ERR_NO_OBSERVATION");
+        if now >= latest.timestamp + OBSERVATION_PERIOD {
+            self.observation_index = (self.observation_index + 1) % OBSERVATION_CAPACITY;
+            self.observations
+                .replace(self.observation_index, &self.last);
+        }
+    }
+
+    /// Returns average amount of `token_out` per unit of `token_in` (in `PRICE_PRECISION`)
+    /// over at least last `window` nanoseconds, given current pool `amounts`.
+    /// Uses the most recent snapshot taken no later than `window` ago.
+    pub fn get_twap(
+        &self,
+        amounts: &[Balance],
+        token_in: usize,
+        token_out: usize,
+        window: Timestamp,
+    ) -> u128 {
+        let now = env::block_timestamp();
+        assert!(window > 0 && window <= now, "{}", ERR90_TWAP_WINDOW_ILLEGAL);
+        let start = self
+            .observations
+            .iter()
+            .filter(|observation| {
+                observation.timestamp != 0 && observation.timestamp <= now - window
+            })
+            .max_by_key(|observation| observation.timestamp)
+            .expect(ERR91_TWAP_INSUFFICIENT_HISTORY);
+        let index = pair_index(amounts.len(), token_in, token_out);
+        let current = if amounts.iter().all(|amount| *amount > 0) {
+            self.cumulatives_at(amounts, now)[index]
+        } else {
+            self.last.price_cumulatives[index]
+        };
+        let elapsed = U256::from(now - start.timestamp);
+        (current.overflowing_sub(start.price_cumulatives[index]).0 / elapsed).as_u128()
+    }
+
+    /// Returns accumulators extrapolated from the last update up to `now` with constant `amounts`.
+    fn cumulatives_at(&self, amounts: &[Balance], now: Timestamp) -> Vec<U256> {
+        let elapsed = U256::from(now - self.last.timestamp);
+        let num_tokens = amounts.len();
+        let mut cumulatives = self.last.price_cumulatives.clone();
+        for token_in in 0..num_tokens {
+            for token_out in 0..num_tokens {
+                if token_in == token_out {
+                    continue;
+                }
+                let price = U256::from(amounts[token_out]) * U256::from(PRICE_PRECISION)
+                    / U256::from(amounts[token_in]);
+                let index = pair_index(num_tokens, token_in, token_out);
+                cumulatives[index] = cumulatives[index]
+                    .overflowing_add(price.overflowing_mul(elapsed).0)
+                    .0;
+            }
+        }
+        cumulatives
+    }
+}
+
+/// Position of ordered pair (token_in, token_out) among the `num_tokens * (num_tokens - 1)` accumulators.
+fn pair_index(num_tokens: usize, token_in: usize, token_out: usize) -> usize {
+    assert_ne!(token_in, token_out, "{}", ERR71_SWAP_DUP_TOKENS);
+    token_in * (num_tokens - 1)
+        + if token_out < token_in {
+            token_out
+        } else {
+            token_out - 1
+        }
+}
@@ -1,107 +1,86 @@
 //! implement all relevant logic for owner of this contract.
 
-use near_contract_standards::fungible_token::core_impl::ext_fungible_token;
-use near_sdk::env::predecessor_account_id;
 use near_sdk::json_types::WrappedTimestamp;
 
-use crate::utils::{FEE_DIVISOR, GAS_FOR_BASIC_OP};
+use crate::utils::FEE_DIVISOR;
 use crate::errors::*;
+use crate::legacy::ContractV1;
 use crate::*;
 
 #[near_bindgen]
 impl Contract {
-    /// Change owner. Only can be called by owner.
-    pub fn set_owner(&mut self, owner_id: ValidAccountId) {
-        self.assert_owner();
-        self.owner_id = owner_id.as_ref().clone();
-    }
-
     /// Get owner of this account.
     pub fn get_owner(&self) -> AccountId {
         self.owner_id.clone()
     }
 
-    /// Retrieve NEP-141 tokens that not managed by contract to owner,
-    /// Caution: Must check that `amount <= total_amount_in_account - amount_managed_by_contract` before calling !!!
-    /// Returns promise of ft_transfer action.
-    #[payable]
-    pub fn retrieve_unmanaged_token(&mut self, token_id: ValidAccountId, amount: U128) -> Promise {
-        self.assert_owner();
-        assert_one_yocto();
-        let token_id: AccountId = token_id.into();
-        let amount: u128 = amount.into();
-        assert!(amount > 0, "{}", ERR29_ILLEGAL_WITHDRAW_AMOUNT);
-        env::log(
-            format!(
-                "Going to retrieve token {} to owner, amount: {}",
-                &token_id, amount
-            )
-            .as_bytes(),
-        );
-
-        ext_fungible_token::ft_transfer(
-            self.owner_id.clone(),
-            U128(amount),
-            None,
-            &token_id,
-            1,
-            env::prepaid_gas() - GAS_FOR_BASIC_OP,
-        )
-    }
-
-    /// Change state of contract, Only can be called by owner or guardians.
+    /// Change state of contract, Only can be called by pause managers.
+    /// Guardians can also switch it from running to withdraw only mode.
     #[payable]
     pub fn change_state(&mut self, state: RunningState) {
         assert_one_yocto();
-        assert!(self.is_owner_or_guardians(), "{}", ERR100_NOT_ALLOWED);
+        if self.state == RunningState::Running && state == RunningState::WithdrawOnly {
+            self.assert_guardian_or_role(Role::PauseManager);
+        } else {
+            self.assert_role(Role::PauseManager);
+        }
 
         if self.state != state {
-            if state == RunningState::Running {
-                // only owner can resume the contract
+            if state == RunningState::Running || self.state == RunningState::WithdrawOnly {
+                // only owner can resume the contract or leave withdraw only mode
                 self.assert_owner();
             }
-            env::log(
-                format!(
-                    "Contract state changed from {} to {} by {}",
-                    self.state,
-                    state,
-                    env::predecessor_account_id()
-                )
-                .as_bytes(),
-            );
+            Event::StateChanged {
+                from: self.state.to_string(),
+                to: state.to_string(),
+                changed_by: &env::predecessor_account_id(),
+            }
+            .emit();
             self.state = state;
         }
     }
 
-    /// Extend whitelisted tokens with new tokens. Only can be called by owner.
+    /// Extend whitelisted tokens with new tokens. Only can be called by token managers.
     #[payable]
     pub fn extend_whitelisted_tokens(&mut self, tokens: Vec<ValidAccountId>) {
-        assert!(self.is_owner_or_guardians(), "ERR_NOT_ALLOWED");
+        self.assert_role(Role::TokenManager);
         for token in tokens {
             self.whitelisted_tokens.insert(token.as_ref());
         }
     }
-    /// Remove whitelisted token. Only can be called by owner.
+    /// Remove whitelisted token. Only can be called by token managers.
+    #[payable]
     pub fn remove_whitelisted_tokens(&mut self, tokens: Vec<ValidAccountId>) {
-        assert!(self.is_owner_or_guardians(), "ERR_NOT_ALLOWED");
+        assert_one_yocto();
+        self.assert_role(Role::TokenManager);
         for token in tokens {
             self.whitelisted_tokens.remove(token.as_ref());
         }
     }
 
-    pub fn modify_admin_fee(&mut self, exchange_fee: u32, referral_fee: u32) {
-        self.assert_owner();
-        assert!(
-            exchange_fee + referral_fee <= FEE_DIVISOR,
-            "ERR_ILLEGAL_FEE"
-        );
-        self.exchange_fee = exchange_fee;
-        self.referral_fee = referral_fee;
+    /// Allows creating simple pools with given fee (in bps). Only can be called by fee managers.
+    #[payable]
+    pub fn add_fee_tier(&mut self, fee: u32) {
+        assert_one_yocto();
+        self.assert_role(Role::FeeManager);
+        assert!(fee < FEE_DIVISOR, "{}", ERR101_ILLEGAL_FEE);
+        match self.fee_tiers.binary_search(&fee) {
+            Ok(_) => env::panic(ERR89_FEE_TIER_EXISTED.as_bytes()),
+            Err(index) => self.fee_tiers.insert(index, fee),
+        }
     }
 
-    pub(crate) fn is_owner_or_guardians(&self) -> bool {
-        env::predecessor_account_id() == self.owner_id
-            || self.guardians.contains(&env::predecessor_account_id())
+    /// Disallows creating simple pools with given fee. Existing pools with this fee are kept.
+    /// Only can be called by fee managers.
+    #[payable]
+    pub fn remove_fee_tier(&mut self, fee: u32) {
+        assert_one_yocto();
+        self.assert_role(Role::FeeManager);
+        let index = self
+            .fee_tiers
+            .binary_search(&fee)
+            .unwrap_or_else(|_| env::panic(ERR88_FEE_TIER_NOT_ALLOWED.as_bytes()));
+        self.fee_tiers.remove(index);
     }
 
     pub(crate) fn assert_owner(&self) {
@@ -112,23 +91,43 @@ impl Contract {
         );
     }
 
-    /// Extend guardians. Only can be called by owner.
-    #[payable]
-    pub fn extend_guardians(&mut self, guardians: Vec<ValidAccountId>) {
-        assert_one_yocto();
-        self.assert_owner();
-        for guardian in guardians {
-            self.guardians.insert(guardian.as_ref());
+    /// Starts ramping amp factor of given stable swap pool towards `future_amp_factor`,
+    /// reaching it at `future_amp_time` (in nanoseconds). Only can be called by pool creators.
+    pub fn stable_swap_ramp_amp(
+        &mut self,
+        pool_id: u64,
+        future_amp_factor: u64,
+        future_amp_time: WrappedTimestamp,
+    ) {
+        self.assert_role(Role::PoolCreator);
+        let mut pool = self.internal_get_pool(pool_id).expect(ERR85_NO_POOL);
+        match &mut pool {
+            Pool::StableSwapPool(pool) => {
+                pool.ramp_amplification(future_amp_factor as u128, future_amp_time.0)
+            }
+            _ => env::panic(ERR86_UNSUPPORTED_POOL_KIND.as_bytes()),
+        }
+        self.internal_save_pool(pool_id, pool);
+    }
+
+    /// Stops ongoing amp ramping of given stable swap pool. Only can be called by pool creators.
+    pub fn stable_swap_stop_ramp_amp(&mut self, pool_id: u64) {
+        self.assert_role(Role::PoolCreator);
+        let mut pool = self.internal_get_pool(pool_id).expect(ERR85_NO_POOL);
+        match &mut pool {
+            Pool::StableSwapPool(pool) => pool.stop_ramp_amplification(),
+            _ => env::panic(ERR86_UNSUPPORTED_POOL_KIND.as_bytes()),
         }
+        self.internal_save_pool(pool_id, pool);
     }
 
-    /// Migration function from v2 to v2.
+    /// Migration function from v1 to v2, pools are converted afterwards with `migrate_pools`.
     /// For next version upgrades, change this function.
     #[init(ignore_state)]
     // [AUDIT_09]
     #[private]
     pub fn migrate() -> Self {
-        let contract: Contract = env::state_read().expect(ERR103_NOT_INITIALIZED);
-        contract
+        let contract: ContractV1 = env::state_read().expect(ERR103_NOT_INITIALIZED);
+        contract.into_current()
     }
 }
@@ -1,14 +1,63 @@
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::json_types::ValidAccountId;
-use near_sdk::{AccountId, Balance};
+use near_sdk::{env, AccountId, Balance};
 
 use crate::admin_fee::AdminFees;
+use crate::errors::{ERR63_MISSING_TOKEN, ERR86_UNSUPPORTED_POOL_KIND, ERR96_FLASH_LOAN_AMOUNT};
+use crate::legacy::SimplePoolV1;
+use crate::oracle::PriceOracle;
 use crate::simple_pool::SimplePool;
+use crate::stable_swap_math::TARGET_DECIMAL;
+use crate::stable_swap_pool::StableSwapPool;
 use crate::utils::SwapVolume;
+use crate::weighted_pool::WeightedPool;
 
 #[derive(BorshDeserialize, BorshSerialize)]
+#[allow(clippy::enum_variant_names)]
 pub enum Pool {
     SimplePool(SimplePool),
+    StableSwapPool(StableSwapPool),
+    WeightedPool(WeightedPool),
+}
+
+/// Versioned pool record. Variants before `Current` are layouts of previous versions, kept at
+/// the positions they had in `Pool` of those versions, so pools stored by them are read as is.
+/// They are converted to `Current` in batches by `migrate_pools`.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub enum VPool {
+    SimplePoolV1(SimplePoolV1),
+    Current(Pool),
+}
+
+impl VPool {
+    pub fn is_current(&self) -> bool {
+        matches!(self, VPool::Current(_))
+    }
+
+    /// Converts pool to the current version for reading, state added since is left unset.
+    pub fn into_current(self) -> Pool {
+        match self {
+            VPool::SimplePoolV1(pool) => Pool::SimplePool(pool.into_current()),
+            VPool::Current(pool) => pool,
+        }
+    }
+
+    /// Converts pool to the current version for storing, setting up state added since.
+    /// Pools changed before their migration are already current, but still miss that state.
+    pub fn migrate(self, pool_id: u64) -> Pool {
+        let mut pool = self.into_current();
+        if let Pool::SimplePool(pool) = &mut pool {
+            if pool.oracle.is_none() && pool.token_account_ids.len() == 2 {
+                pool.oracle = Some(PriceOracle::new(pool_id as u32, 2));
+            }
+        }
+        pool
+    }
+}
+
+impl From<Pool> for VPool {
+    fn from(pool: Pool) -> Self {
+        VPool::Current(pool)
+    }
 }
 
 impl Pool {
@@ -16,6 +65,8 @@ impl Pool {
     pub fn kind(&self) -> String {
         match self {
             Pool::SimplePool(_) => "SIMPLE_POOL".to_string(),
+            Pool::StableSwapPool(_) => "STABLE_SWAP".to_string(),
+            Pool::WeightedPool(_) => "WEIGHTED_POOL".to_string(),
         }
     }
 
@@ -23,14 +74,71 @@ impl Pool {
     pub fn tokens(&self) -> &[AccountId] {
         match self {
             Pool::SimplePool(pool) => pool.tokens(),
+            Pool::StableSwapPool(pool) => pool.tokens(),
+            Pool::WeightedPool(pool) => pool.tokens(),
+        }
+    }
+
+    fn amounts_mut(&mut self) -> &mut Vec<Balance> {
+        match self {
+            Pool::SimplePool(pool) => &mut pool.amounts,
+            Pool::StableSwapPool(pool) => &mut pool.amounts,
+            Pool::WeightedPool(pool) => &mut pool.amounts,
+        }
+    }
+
+    fn token_index(&self, token_id: &AccountId) -> usize {
+        self.tokens()
+            .iter()
+            .position(|id| id == token_id)
+            .expect(ERR63_MISSING_TOKEN)
+    }
+
+    /// Takes given amount of token out of the pool reserves to lend it out.
+    /// At least some of the token has to stay in the pool.
+    pub fn lend(&mut self, token_id: &AccountId, amount: Balance) {
+        let index = self.token_index(token_id);
+        self.update_oracle();
+        let amounts = self.amounts_mut();
+        assert!(amounts[index] > amount, "{}", ERR96_FLASH_LOAN_AMOUNT);
+        amounts[index] -= amount;
+    }
+
+    /// Puts given amount of token back into the pool reserves.
+    pub fn repay(&mut self, token_id: &AccountId, amount: Balance) {
+        let index = self.token_index(token_id);
+        self.update_oracle();
+        self.amounts_mut()[index] += amount;
+    }
+
+    /// Accumulates prices of the pool oracle before its amounts change outside of the pool.
+    fn update_oracle(&mut self) {
+        if let Pool::SimplePool(pool) = self {
+            pool.update_oracle();
         }
     }
 
     /// Adds liquidity into underlying pool
     /// Updates amounts to amount kept in the pool
-    pub fn add_liquidity(&mut self, sender_id: &AccountId, amounts: &mut Vec<Balance>) -> Balance {
+    pub fn add_liquidity(&mut self, sender_id: &AccountId, amounts: &mut [Balance]) -> Balance {
         match self {
             Pool::SimplePool(pool) => pool.add_liquidity(sender_id, amounts),
+            Pool::WeightedPool(pool) => pool.add_liquidity(sender_id, amounts),
+            Pool::StableSwapPool(_) => env::panic(ERR86_UNSUPPORTED_POOL_KIND.as_bytes()),
+        }
+    }
+
+    /// Adds liquidity into underlying stable swap pool, taking all given amounts.
+    /// Returns number of minted shares.
+    pub fn add_stable_liquidity(
+        &mut self,
+        sender_id: &AccountId,
+        amounts: &[Balance],
+        min_shares: Balance,
+    ) -> Balance {
+        match self {
+            Pool::StableSwapPool(pool) => pool.add_liquidity(sender_id, amounts, min_shares),
+            _ => env::panic(ERR86_UNSUPPORTED_POOL_KIND.as_bytes()),
         }
     }
 
@@ -42,6 +150,8 @@ impl Pool {
     ) -> Vec<Balance> {
         match self {
             Pool::SimplePool(pool) => pool.remove_liquidity(sender_id, shares, min_amounts),
+            Pool::StableSwapPool(pool) => pool.remove_liquidity(sender_id, shares, min_amounts),
+            Pool::WeightedPool(pool) => pool.remove_liquidity(sender_id, shares, min_amounts),
         }
     }
 
@@ -55,10 +165,35 @@ impl Pool {
     ) -> Balance {
         match self {
             Pool::SimplePool(pool) => pool.get_return(token_in, amount_in, token_out),
+            Pool::StableSwapPool(pool) => pool.get_return(token_in, amount_in, token_out),
+            Pool::WeightedPool(pool) => pool.get_return(token_in, amount_in, token_out),
         }
     }
 
-    /// Returns how many tokens will one need to input in order to get the desired token_out
+    /// Same as `get_return`, but returns None where it would fail,
+    /// e.g. for tokens not in the pool, empty reserves or amounts the pool can't take.
+    pub fn try_get_return(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> Option<Balance> {
+        let tokens = self.tokens();
+        if amount_in == 0
+            || token_in == token_out
+            || !tokens.contains(token_in)
+            || !tokens.contains(token_out)
+        {
+            return None;
+        }
+        match self {
+            Pool::SimplePool(pool) => pool.try_get_return(token_in, amount_in, token_out),
+            Pool::StableSwapPool(pool) => pool.try_get_return(token_in, amount_in, token_out),
+            Pool::WeightedPool(pool) => pool.try_get_return(token_in, amount_in, token_out),
+        }
+    }
+
+    /// Returns how many tokens will one need to input in order to get the desired token_out, fee included.
     pub fn get_income(
         &self,
         token_in: &AccountId,
@@ -68,13 +203,16 @@ impl Pool {
     ) -> Balance {
         match self {
             Pool::SimplePool(pool) => pool.get_income(token_in, token_out, amount_out),
+            Pool::StableSwapPool(pool) => pool.get_income(token_in, token_out, amount_out),
+            Pool::WeightedPool(pool) => pool.get_income(token_in, token_out, amount_out),
         }
     }
 
     /// Return share decimal.
     pub fn get_share_decimal(&self) -> u8 {
         match self {
-            Pool::SimplePool(_) => 24,
+            Pool::SimplePool(_) | Pool::WeightedPool(_) => 24,
+            Pool::StableSwapPool(_) => TARGET_DECIMAL,
         }
     }
 
@@ -82,6 +220,8 @@ impl Pool {
     pub fn get_fee(&self) -> u32 {
         match self {
             Pool::SimplePool(pool) => pool.get_fee(),
+            Pool::StableSwapPool(pool) => pool.get_fee(),
+            Pool::WeightedPool(pool) => pool.get_fee(),
         }
     }
 
@@ -89,18 +229,52 @@ impl Pool {
     pub fn get_volumes(&self) -> Vec<SwapVolume> {
         match self {
             Pool::SimplePool(pool) => pool.get_volumes(),
+            Pool::StableSwapPool(pool) => pool.get_volumes(),
+            Pool::WeightedPool(pool) => pool.get_volumes(),
         }
     }
 
     pub fn is_lp(&self, account_id: &AccountId) -> bool {
         match self {
-            Pool::SimplePool(pool) => pool.is_lp(&account_id),
+            Pool::SimplePool(pool) => pool.is_lp(account_id),
+            Pool::StableSwapPool(pool) => pool.is_lp(account_id),
+            Pool::WeightedPool(pool) => pool.is_lp(account_id),
+        }
+    }
+
+    /// Returns current amplification factor, 0 for pools without one.
+    pub fn get_amp(&self) -> u64 {
+        match self {
+            Pool::SimplePool(_) | Pool::WeightedPool(_) => 0,
+            Pool::StableSwapPool(pool) => pool.get_amp(),
+        }
+    }
+
+    /// Returns normalized token weights for weighted pools.
+    pub fn get_weights(&self) -> Option<Vec<u32>> {
+        match self {
+            Pool::WeightedPool(pool) => Some(pool.weights.clone()),
+            _ => None,
         }
     }
 
-    /// Returns given pool's share price in precision 1e8
+    /// Returns value of one whole share (10^share_decimal) in units of the first token of the pool.
+    /// Units are the smallest ones of the token, same as in `amounts`, for every pool kind.
     pub fn get_share_price(&self) -> u128 {
-        unimplemented!()
+        let shares = 10u128.pow(self.get_share_decimal() as u32);
+        match self {
+            Pool::SimplePool(pool) => pool.get_shares_value(shares),
+            Pool::StableSwapPool(pool) => pool.get_shares_value(shares),
+            Pool::WeightedPool(pool) => pool.get_shares_value(shares),
+        }
+    }
+
+    /// Returns time weighted average price of token_in in token_out over last `window_sec` seconds.
+    pub fn get_twap(&self, token_in: &AccountId, token_out: &AccountId, window_sec: u64) -> u128 {
+        match self {
+            Pool::SimplePool(pool) => pool.get_twap(token_in, token_out, window_sec),
+            _ => env::panic(ERR86_UNSUPPORTED_POOL_KIND.as_bytes()),
+        }
     }
 
     /// Swaps given number of token_in for token_out and returns received amount.
@@ -116,40 +290,53 @@ impl Pool {
             Pool::SimplePool(pool) => {
                 pool.swap(token_in, amount_in, token_out, min_amount_out, &admin_fee)
             }
+            Pool::StableSwapPool(pool) => {
+                pool.swap(token_in, amount_in, token_out, min_amount_out, &admin_fee)
+            }
+            Pool::WeightedPool(pool) => {
+                pool.swap(token_in, amount_in, token_out, min_amount_out, &admin_fee)
+            }
         }
     }
 
     pub fn share_total_balance(&self) -> Balance {
         match self {
             Pool::SimplePool(pool) => pool.share_total_balance(),
+            Pool::StableSwapPool(pool) => pool.share_total_balance(),
+            Pool::WeightedPool(pool) => pool.share_total_balance(),
         }
     }
 
     pub fn share_balances(&self, account_id: &AccountId) -> Balance {
         match self {
             Pool::SimplePool(pool) => pool.share_balance_of(account_id),
+            Pool::StableSwapPool(pool) => pool.share_balance_of(account_id),
+            Pool::WeightedPool(pool) => pool.share_balance_of(account_id),
         }
     }
 
     pub fn share_register(&mut self, account_id: &AccountId) {
         match self {
             Pool::SimplePool(pool) => pool.share_register(account_id),
+            Pool::StableSwapPool(pool) => pool.share_register(account_id),
+            Pool::WeightedPool(pool) => pool.share_register(account_id),
         }
     }
 
-    pub fn predict_remove_liquidity(&self, shares: Balance) -> Vec<Balance> {
-        match &self {
-            &Pool::SimplePool(pool) => pool.predict_remove_liquidity(shares),
+    /// Transfers shares between two registered accounts.
+    pub fn share_transfer(&mut self, sender_id: &AccountId, receiver_id: &AccountId, amount: u128) {
+        match self {
+            Pool::SimplePool(pool) => pool.share_transfer(sender_id, receiver_id, amount),
+            Pool::StableSwapPool(pool) => pool.share_transfer(sender_id, receiver_id, amount),
+            Pool::WeightedPool(pool) => pool.share_transfer(sender_id, receiver_id, amount),
         }
     }
 
-    pub fn check_existed_pool(&self, tokens: &Vec<ValidAccountId>) -> bool {
-        let pool_tokens = self.tokens();
-
-        if pool_tokens[0] == tokens[0].to_string() && pool_tokens[1] == tokens[1].to_string() {
-            return true;
-        } else {
-            return false;
+    pub fn predict_remove_liquidity(&self, shares: Balance) -> Vec<Balance> {
+        match self {
+            Pool::SimplePool(pool) => pool.predict_remove_liquidity(shares),
+            Pool::StableSwapPool(pool) => pool.predict_remove_liquidity(shares),
+            Pool::WeightedPool(pool) => pool.predict_remove_liquidity(shares),
         }
     }
 }
@@ -0,0 +1,144 @@
+//! Index of pools by their tokens.
+//!
+//! Every pool is listed under each unordered pair of its tokens and under each of its tokens,
+//! so lookups don't depend on the number of pools. A pair can have several pools, e.g. of
+//! different kinds or fee tiers. Pools of a token are kept in a vector of the token, as a popular
+//! token can be in any number of pools. Pools are also keyed by their sorted tokens, kind and fee,
+//! so the duplicate check on pool creation is a single lookup.
+//! Pools that existed before the index are listed by `migrate_pools`.
+
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct PairFeeTier {
+    pub fee: u32,
+    /// Simple pool of the pair with this fee, if created.
+    pub pool_id: Option<u64>,
+}
+
+/// Sorted tokens, kind and fee of a pool, pools with the same key are duplicates.
+pub type PoolKey = (Vec<AccountId>, String, u32);
+
+pub fn pool_key(pool: &Pool) -> PoolKey {
+    let mut tokens = pool.tokens().to_vec();
+    tokens.sort();
+    (tokens, pool.kind(), pool.get_fee())
+}
+
+/// Key of an unordered pair of tokens.
+fn pair_key(token_a: &AccountId, token_b: &AccountId) -> (AccountId, AccountId) {
+    if token_a < token_b {
+        (token_a.clone(), token_b.clone())
+    } else {
+        (token_b.clone(), token_a.clone())
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Returns ids of pools that contain both given tokens, except deprecated ones.
+    pub fn get_pools_by_pair(&self, token_a: ValidAccountId, token_b: ValidAccountId) -> Vec<u64> {
+        self.internal_get_pools_by_pair(token_a.as_ref(), token_b.as_ref())
+            .into_iter()
+            .filter(|pool_id| !self.is_pool_deprecated(*pool_id))
+            .collect()
+    }
+
+    /// Returns all fee tiers of given pair of tokens with its simple pools.
+    /// Fees of existing pools that are no longer allowed fee tiers are included too.
+    pub fn get_pair_fee_tiers(
+        &self,
+        token_a: ValidAccountId,
+        token_b: ValidAccountId,
+    ) -> Vec<PairFeeTier> {
+        let mut fee_tiers: Vec<PairFeeTier> = self
+            .fee_tiers
+            .iter()
+            .map(|fee| PairFeeTier {
+                fee: *fee,
+                pool_id: None,
+            })
+            .collect();
+        for pool_id in self.internal_get_pools_by_pair(token_a.as_ref(), token_b.as_ref()) {
+            let pool = self.internal_get_pool(pool_id).unwrap();
+            if !matches!(pool, Pool::SimplePool(_))
+                || pool.tokens().len() != 2
+                || self.is_pool_deprecated(pool_id)
+            {
+                continue;
+            }
+            let fee = pool.get_fee();
+            match fee_tiers.binary_search_by_key(&fee, |tier| tier.fee) {
+                Ok(index) => fee_tiers[index].pool_id = Some(pool_id),
+                Err(index) => fee_tiers.insert(
+                    index,
+                    PairFeeTier {
+                        fee,
+                        pool_id: Some(pool_id),
+                    },
+                ),
+            }
+        }
+        fee_tiers
+    }
+
+    /// Returns ids of pools that contain given token, except deprecated ones,
+    /// `limit` of them from `from_index`.
+    pub fn get_pools_by_token(
+        &self,
+        token_id: ValidAccountId,
+        from_index: u64,
+        limit: u64,
+    ) -> Vec<u64> {
+        match self.token_pools.get(token_id.as_ref()) {
+            Some(pool_ids) => pool_ids
+                .iter()
+                .filter(|pool_id| !self.is_pool_deprecated(*pool_id))
+                .skip(from_index as usize)
+                .take(limit as usize)
+                .collect(),
+            None => vec![],
+        }
+    }
+}
+
+impl Contract {
+    pub(crate) fn is_pool_deprecated(&self, pool_id: u64) -> bool {
+        self.internal_get_pool_state(pool_id) == PoolState::Deprecated
+    }
+
+    pub(crate) fn internal_get_pools_by_pair(
+        &self,
+        token_a: &AccountId,
+        token_b: &AccountId,
+    ) -> Vec<u64> {
+        self.pair_pools
+            .get(&pair_key(token_a, token_b))
+            .unwrap_or_default()
+    }
+
+    /// Lists given pool under each pair of its tokens, each of its tokens and its key.
+    pub(crate) fn internal_index_pool(&mut self, pool_id: u64, pool: &Pool) {
+        self.pool_keys.insert(&pool_key(pool), &pool_id);
+        let tokens = pool.tokens();
+        for (i, token_a) in tokens.iter().enumerate() {
+            for token_b in tokens.iter().skip(i + 1) {
+                let key = pair_key(token_a, token_b);
+                let mut pool_ids = self.pair_pools.get(&key).unwrap_or_default();
+                pool_ids.push(pool_id);
+                self.pair_pools.insert(&key, &pool_ids);
+            }
+            let mut pool_ids = self.token_pools.get(token_a).unwrap_or_else(|| {
+                Vector::new(StorageKey::TokenPoolIds {
+                    token_id: token_a.clone(),
+                })
+            });
+            pool_ids.push(&pool_id);
+            self.token_pools.insert(token_a, &pool_ids);
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! Running state of each pool, so an incident in one pool doesn't halt the others.
+//!
+//! States are ordered from the least to the most restrictive. Guardians and pause managers can
+//! move a pool to a more restrictive state, only owner can move it back. Liquidity can be removed in any state.
+//! Deprecated pools are left out of the pool index lookups, so a new pool can replace them.
+//! Deprecation is final, as the replacing pool may already exist, so guardians can't deprecate pools.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::roles::Role;
+use crate::*;
+
+#[derive(
+    BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Copy, PartialEq, PartialOrd,
+)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub enum PoolState {
+    Running,
+    /// Liquidity can be added and removed, but not swapped.
+    SwapsPaused,
+    /// Liquidity can only be removed.
+    WithdrawOnly,
+    /// Liquidity can only be removed, the pool is not used anymore.
+    Deprecated,
+}
+
+impl fmt::Display for PoolState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PoolState::Running => write!(f, "Running"),
+            PoolState::SwapsPaused => write!(f, "SwapsPaused"),
+            PoolState::WithdrawOnly => write!(f, "WithdrawOnly"),
+            PoolState::Deprecated => write!(f, "Deprecated"),
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Changes state of given pool. Guardians and pause managers can only make it more restrictive,
+    /// owner can change it either way. Only pause managers can deprecate pools, which is final.
+    #[payable]
+    pub fn set_pool_state(&mut self, pool_id: u64, state: PoolState) {
+        assert_one_yocto();
+        if state == PoolState::Deprecated {
+            self.assert_role(Role::PauseManager);
+        } else {
+            self.assert_guardian_or_role(Role::PauseManager);
+        }
+        assert!(pool_id < self.pools.len(), "{}", ERR85_NO_POOL);
+        let prev_state = self.internal_get_pool_state(pool_id);
+        assert!(
+            prev_state != PoolState::Deprecated,
+            "{}",
+            ERR78_POOL_DEPRECATED
+        );
+        if prev_state == state {
+            return;
+        }
+        assert!(
+            state > prev_state || env::predecessor_account_id() == self.owner_id,
+            "{}",
+            ERR100_NOT_ALLOWED
+        );
+        Event::PoolStateChanged {
+            pool_id,
+            from: prev_state.to_string(),
+            to: state.to_string(),
+            changed_by: &env::predecessor_account_id(),
+        }
+        .emit();
+        if state == PoolState::Running {
+            self.pool_states.remove(&pool_id);
+        } else {
+            self.pool_states.insert(&pool_id, &state);
+        }
+    }
+
+    pub fn get_pool_state(&self, pool_id: u64) -> PoolState {
+        assert!(pool_id < self.pools.len(), "{}", ERR85_NO_POOL);
+        self.internal_get_pool_state(pool_id)
+    }
+}
+
+impl Contract {
+    pub(crate) fn internal_get_pool_state(&self, pool_id: u64) -> PoolState {
+        self.pool_states.get(&pool_id).unwrap_or(PoolState::Running)
+    }
+
+    /// Panics unless given pool can be swapped in.
+    pub(crate) fn assert_pool_running(&self, pool_id: u64) {
+        assert!(
+            self.internal_get_pool_state(pool_id) == PoolState::Running,
+            "{}",
+            ERR75_POOL_NOT_RUNNING
+        );
+    }
+
+    /// Panics unless liquidity can be added to given pool.
+    pub(crate) fn assert_pool_accepts_liquidity(&self, pool_id: u64) {
+        assert!(
+            self.internal_get_pool_state(pool_id) <= PoolState::SwapsPaused,
+            "{}",
+            ERR76_POOL_LIQUIDITY_CLOSED
+        );
+    }
+}
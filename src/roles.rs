@@ -0,0 +1,113 @@
+//! Roles giving access to admin methods.
+//!
+//! Owner has every role and grants or revokes them to other accounts. Guardians don't have any role
+//! by being guardians, they veto admin proposals of the timelock, pause single pools and switch the
+//! contract to withdraw only mode.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub enum Role {
+    /// Pauses the contract and pools, deprecates pools and removes flash loan borrowers.
+    PauseManager,
+    /// Manages whitelisted tokens.
+    TokenManager,
+    /// Manages fee tiers of simple pools.
+    FeeManager,
+    /// Creates stable swap and weighted pools and ramps amp factor of stable swap pools.
+    PoolCreator,
+    /// Upgrades the contract code.
+    Upgrader,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Grants role to given account. Only can be called by owner.
+    #[payable]
+    pub fn grant_role(&mut self, role: Role, account_id: ValidAccountId) {
+        assert_one_yocto();
+        self.assert_owner();
+        let mut roles = self.roles.get(account_id.as_ref()).unwrap_or_default();
+        if !roles.contains(&role) {
+            roles.push(role);
+            self.roles.insert(account_id.as_ref(), &roles);
+        }
+    }
+
+    /// Revokes role from given account. Only can be called by owner.
+    #[payable]
+    pub fn revoke_role(&mut self, role: Role, account_id: ValidAccountId) {
+        assert_one_yocto();
+        self.assert_owner();
+        if let Some(mut roles) = self.roles.get(account_id.as_ref()) {
+            roles.retain(|r| *r != role);
+            if roles.is_empty() {
+                self.roles.remove(account_id.as_ref());
+            } else {
+                self.roles.insert(account_id.as_ref(), &roles);
+            }
+        }
+    }
+
+    /// Returns roles granted to given account, owner's ones are not listed.
+    pub fn get_account_roles(&self, account_id: ValidAccountId) -> Vec<Role> {
+        self.roles.get(account_id.as_ref()).unwrap_or_default()
+    }
+
+    /// Returns accounts given role is granted to, besides owner.
+    pub fn get_role_members(&self, role: Role) -> Vec<AccountId> {
+        self.roles
+            .iter()
+            .filter(|(_, roles)| roles.contains(&role))
+            .map(|(account_id, _)| account_id)
+            .collect()
+    }
+
+    /// Returns all accounts with granted roles.
+    pub fn get_roles(&self) -> HashMap<AccountId, Vec<Role>> {
+        self.roles.iter().collect()
+    }
+
+    pub fn has_role(&self, role: Role, account_id: ValidAccountId) -> bool {
+        self.internal_has_role(account_id.as_ref(), role)
+    }
+
+    pub fn get_guardians(&self) -> Vec<AccountId> {
+        self.guardians.to_vec()
+    }
+}
+
+impl Contract {
+    /// Owner has every role.
+    fn internal_has_role(&self, account_id: &AccountId, role: Role) -> bool {
+        account_id == &self.owner_id
+            || self
+                .roles
+                .get(account_id)
+                .is_some_and(|roles| roles.contains(&role))
+    }
+
+    /// Panics unless predecessor has given role.
+    pub(crate) fn assert_role(&self, role: Role) {
+        assert!(
+            self.internal_has_role(&env::predecessor_account_id(), role),
+            "{}",
+            ERR100_NOT_ALLOWED
+        );
+    }
+
+    /// Panics unless predecessor is a guardian or has given role.
+    pub(crate) fn assert_guardian_or_role(&self, role: Role) {
+        let account_id = env::predecessor_account_id();
+        assert!(
+            self.guardians.contains(&account_id) || self.internal_has_role(&account_id, role),
+            "{}",
+            ERR100_NOT_ALLOWED
+        );
+    }
+}
@@ -0,0 +1,286 @@
+//! Routing of swaps through the pool graph.
+//!
+//! Candidate paths of up to `MAX_ROUTE_HOPS` pools between two tokens are found with the pool
+//! index, shortest first. Amount is then split into `ROUTE_SPLIT_PARTS` equal parts, each given to
+//! the path that adds most output for it. Once a path got a part, paths sharing any of its pools
+//! are not used, so swaps along the chosen paths don't change each other's quotes.
+//! The split is only taken if it returns more than the best single path.
+
+use std::collections::HashMap;
+
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+/// Max number of pools in a path.
+pub const MAX_ROUTE_HOPS: usize = 3;
+/// Max number of candidate paths quoted.
+pub const MAX_ROUTE_CANDIDATES: usize = 20;
+/// Max number of path extensions explored while looking for candidates, bounds gas of the search.
+pub const MAX_ROUTE_EXPANSIONS: usize = 200;
+/// Number of parts amount is split into, which also bounds number of parallel paths.
+pub const ROUTE_SPLIT_PARTS: u128 = 4;
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct RouteHop {
+    pub pool_id: u64,
+    pub token_in: AccountId,
+    pub token_out: AccountId,
+}
+
+/// Part of the amount swapped along one path.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct RouteSplit {
+    pub amount_in: U128,
+    pub amount_out: U128,
+    pub hops: Vec<RouteHop>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct RouteQuote {
+    pub amount_in: U128,
+    pub amount_out: U128,
+    pub splits: Vec<RouteSplit>,
+}
+
+/// Pools read while routing, so each is read from storage once.
+struct PoolCache<'a> {
+    contract: &'a Contract,
+    pools: HashMap<u64, Pool>,
+}
+
+impl PoolCache<'_> {
+    fn get(&mut self, pool_id: u64) -> &Pool {
+        let contract = self.contract;
+        self.pools
+            .entry(pool_id)
+            .or_insert_with(|| contract.internal_get_pool(pool_id).expect(ERR85_NO_POOL))
+    }
+
+    /// Returns amount received by swapping `amount_in` along given path, None if any hop fails.
+    fn quote(&mut self, hops: &[RouteHop], amount_in: Balance) -> Option<Balance> {
+        let mut amount = amount_in;
+        for hop in hops {
+            amount = self
+                .get(hop.pool_id)
+                .try_get_return(&hop.token_in, amount, &hop.token_out)
+                .filter(|amount_out| *amount_out > 0)?;
+        }
+        Some(amount)
+    }
+}
+
+fn shares_pool(a: &[RouteHop], b: &[RouteHop]) -> bool {
+    a.iter().any(|x| b.iter().any(|y| x.pool_id == y.pool_id))
+}
+
+/// Splits `amount_in` in equal parts, giving each to the path that adds most output for it,
+/// among paths that don't share pools with the ones already used.
+/// Returns `(amount_in, amount_out)` of each path, None if some part can't be swapped.
+fn split_amount(
+    cache: &mut PoolCache,
+    candidates: &[Vec<RouteHop>],
+    amount_in: Balance,
+) -> Option<Vec<(Balance, Balance)>> {
+    let mut allocated = vec![(0, 0); candidates.len()];
+    let part = amount_in / ROUTE_SPLIT_PARTS;
+    for i in 0..ROUTE_SPLIT_PARTS {
+        let amount = if i + 1 == ROUTE_SPLIT_PARTS {
+            amount_in - part * (ROUTE_SPLIT_PARTS - 1)
+        } else {
+            part
+        };
+        if amount == 0 {
+            continue;
+        }
+        let mut best: Option<(usize, Balance, Balance)> = None;
+        for (c, hops) in candidates.iter().enumerate() {
+            let (prev_in, prev_out) = allocated[c];
+            let available = prev_in > 0
+                || candidates
+                    .iter()
+                    .zip(allocated.iter())
+                    .all(|(other, (other_in, _))| *other_in == 0 || !shares_pool(hops, other));
+            if !available {
+                continue;
+            }
+            if let Some(out) = cache.quote(hops, prev_in + amount) {
+                let gain = out.saturating_sub(prev_out);
+                if best.is_none_or(|(_, _, best_gain)| gain > best_gain) {
+                    best = Some((c, out, gain));
+                }
+            }
+        }
+        let (c, out, _) = best?;
+        allocated[c] = (allocated[c].0 + amount, out);
+    }
+    Some(allocated)
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Returns the route `Action::Route` would take to swap `amount_in` of `token_in` into `token_out`
+    /// at the current state of the pools.
+    pub fn quote_best_route(
+        &self,
+        token_in: ValidAccountId,
+        token_out: ValidAccountId,
+        amount_in: U128,
+    ) -> RouteQuote {
+        self.internal_quote_best_route(token_in.as_ref(), token_out.as_ref(), amount_in.0)
+            .expect(ERR130_NO_ROUTE)
+    }
+}
+
+impl Contract {
+    /// Swaps along the best route, checking `min_amount_out` against the total received.
+    pub(crate) fn internal_route_swap(
+        &mut self,
+        sender_id: &AccountId,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+        min_amount_out: Balance,
+        referral_id: &Option<AccountId>,
+    ) -> Balance {
+        let route = self
+            .internal_quote_best_route(token_in, token_out, amount_in)
+            .expect(ERR130_NO_ROUTE);
+        let mut amount_out = 0;
+        for split in route.splits {
+            let mut amount = split.amount_in.0;
+            for hop in split.hops {
+                amount = self.internal_pool_swap(
+                    sender_id,
+                    hop.pool_id,
+                    &hop.token_in,
+                    amount,
+                    &hop.token_out,
+                    0,
+                    referral_id,
+                );
+            }
+            amount_out += amount;
+        }
+        assert!(amount_out >= min_amount_out, "{}", ERR68_SLIPPAGE);
+        amount_out
+    }
+
+    fn internal_quote_best_route(
+        &self,
+        token_in: &AccountId,
+        token_out: &AccountId,
+        amount_in: Balance,
+    ) -> Option<RouteQuote> {
+        assert_ne!(token_in, token_out, "{}", ERR71_SWAP_DUP_TOKENS);
+        assert!(amount_in > 0, "{}", ERR31_ZERO_AMOUNT);
+        let mut cache = PoolCache {
+            contract: self,
+            pools: HashMap::new(),
+        };
+        let candidates = self.internal_find_paths(&mut cache, token_in, token_out);
+
+        // Best single path, the shortest one on ties.
+        let (best, best_out) = candidates
+            .iter()
+            .enumerate()
+            .rev()
+            .filter_map(|(i, hops)| cache.quote(hops, amount_in).map(|out| (i, out)))
+            .max_by_key(|(_, out)| *out)?;
+
+        // Split between pool-disjoint paths, taken if it returns more.
+        let allocated = split_amount(&mut cache, &candidates, amount_in)
+            .unwrap_or_else(|| vec![(0, 0); candidates.len()]);
+        let split_out: Balance = allocated.iter().map(|(_, out)| out).sum();
+
+        let splits = if split_out > best_out {
+            candidates
+                .into_iter()
+                .zip(allocated)
+                .filter(|(_, (amount, _))| *amount > 0)
+                .map(|(hops, (amount, out))| RouteSplit {
+                    amount_in: U128(amount),
+                    amount_out: U128(out),
+                    hops,
+                })
+                .collect()
+        } else {
+            vec![RouteSplit {
+                amount_in: U128(amount_in),
+                amount_out: U128(best_out),
+                hops: candidates[best].clone(),
+            }]
+        };
+        Some(RouteQuote {
+            amount_in: U128(amount_in),
+            amount_out: U128(std::cmp::max(split_out, best_out)),
+            splits,
+        })
+    }
+
+    /// Returns paths from `token_in` to `token_out`, shortest first, through pools that can be traded.
+    /// A path doesn't visit any token or pool twice.
+    fn internal_find_paths(
+        &self,
+        cache: &mut PoolCache,
+        token_in: &AccountId,
+        token_out: &AccountId,
+    ) -> Vec<Vec<RouteHop>> {
+        let mut candidates = vec![];
+        let mut frontier: Vec<Vec<RouteHop>> = vec![vec![]];
+        let mut expansions = 0;
+        'search: for _ in 0..MAX_ROUTE_HOPS {
+            let mut next_frontier = vec![];
+            for path in frontier {
+                let current = path.last().map_or(token_in, |hop| &hop.token_out);
+                let pool_ids = match self.token_pools.get(current) {
+                    Some(pool_ids) => pool_ids,
+                    None => continue,
+                };
+                for pool_id in pool_ids.iter() {
+                    if self.flash_loans.contains_key(&pool_id)
+                        || !self.is_pool_migrated(pool_id)
+                        || self.internal_get_pool_state(pool_id) != PoolState::Running
+                        || path.iter().any(|hop| hop.pool_id == pool_id)
+                    {
+                        continue;
+                    }
+                    for next in cache.get(pool_id).tokens() {
+                        if next == current
+                            || next == token_in
+                            || path.iter().any(|hop| &hop.token_out == next)
+                        {
+                            continue;
+                        }
+                        expansions += 1;
+                        if expansions > MAX_ROUTE_EXPANSIONS {
+                            break 'search;
+                        }
+                        let mut hops = path.clone();
+                        hops.push(RouteHop {
+                            pool_id,
+                            token_in: current.clone(),
+                            token_out: next.clone(),
+                        });
+                        if next == token_out {
+                            candidates.push(hops);
+                            if candidates.len() == MAX_ROUTE_CANDIDATES {
+                                break 'search;
+                            }
+                        } else if hops.len() < MAX_ROUTE_HOPS {
+                            next_frontier.push(hops);
+                        }
+                    }
+                }
+            }
+            frontier = next_frontier;
+        }
+        candidates
+    }
+}
@@ -1,17 +1,21 @@
 use std::cmp::min;
 
 use crate::admin_fee::AdminFees;
+use crate::oracle::PriceOracle;
 use crate::StorageKey;
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::collections::UnorderedMap;
 use near_sdk::json_types::ValidAccountId;
-use near_sdk::serde::Serialize;
 use near_sdk::{env, AccountId, Balance};
-use crate::*;
-use crate::errors::{ERR14_LP_ALREADY_REGISTERED, ERR31_ZERO_AMOUNT, ERR32_ZERO_SHARES};
+use crate::errors::{
+    ERR13_LP_NOT_REGISTERED, ERR14_LP_ALREADY_REGISTERED, ERR31_ZERO_AMOUNT, ERR32_ZERO_SHARES,
+    ERR34_INSUFFICIENT_LP_SHARES, ERR64_TOKENS_COUNT_ILLEGAL, ERR65_INIT_TOKEN_BALANCE,
+    ERR86_UNSUPPORTED_POOL_KIND, ERR90_TWAP_WINDOW_ILLEGAL,
+};
 
 use crate::utils::{
-    add_to_collection, uint_sqrt, SwapVolume, FEE_DIVISOR, INIT_SHARES_SUPPLY, NUM_TOKENS, U256,
+    add_to_collection, uint_sqrt, SwapVolume, FEE_DIVISOR, INIT_SHARES_SUPPLY, MAX_NUM_TOKENS,
+    MIN_NUM_TOKENS, U256,
 };
 
 #[derive(BorshSerialize, BorshDeserialize)]
@@ -24,45 +28,34 @@ pub struct SimplePool {
     pub volumes: Vec<SwapVolume>,
     /// Fee charged for swap (gets divided by FEE_DIVISOR).
     pub total_fee: u32,
-    /// Obsolete, reserve to simplify upgrade.
-    pub exchange_fee: u32,
-    /// Obsolete, reserve to simplify upgrade.
-    pub referral_fee: u32,
     /// Shares of the pool by liquidity providers.
     pub shares: UnorderedMap<AccountId, Balance>,
     /// Total number of shares.
     pub shares_total_supply: Balance,
-
-    first_provider: Option<AccountId>
+    /// Time weighted average prices of the pool tokens, only kept by pools of two tokens.
+    pub oracle: Option<PriceOracle>,
 }
 
 impl SimplePool {
-    pub fn new(
-        id: u32,
-        token_account_ids: Vec<ValidAccountId>,
-        total_fee: u32,
-        exchange_fee: u32,
-        referral_fee: u32,
-    ) -> Self {
+    pub fn new(id: u32, token_account_ids: Vec<ValidAccountId>, total_fee: u32) -> Self {
         assert!(total_fee < FEE_DIVISOR, "ERR_FEE_TOO_LARGE");
 
         // [AUDIT_10]
-        assert_eq!(
-            token_account_ids.len(),
-            NUM_TOKENS,
-            "ERR_SHOULD_HAVE_2_TOKENS"
+        assert!(
+            (MIN_NUM_TOKENS..=MAX_NUM_TOKENS).contains(&token_account_ids.len()),
+            "{}",
+            ERR64_TOKENS_COUNT_ILLEGAL
         );
         Self {
             token_account_ids: token_account_ids.iter().map(|a| a.clone().into()).collect(),
             amounts: vec![0u128; token_account_ids.len()],
             volumes: vec![SwapVolume::default(); token_account_ids.len()],
             total_fee,
-            exchange_fee,
-            referral_fee,
             // [AUDIT 11]
             shares: UnorderedMap::new(StorageKey::Shares { pool_id: id }),
             shares_total_supply: 0,
-            first_provider: None,
+            oracle: (token_account_ids.len() == 2)
+                .then(|| PriceOracle::new(id, token_account_ids.len())),
         }
     }
 
@@ -81,6 +74,22 @@ impl SimplePool {
         self.shares.get(account_id).unwrap_or_default()
     }
 
+    /// Transfers shares from predecessor to receiver.
+    /// Receiver has to be registered, so no storage is allocated here.
+    pub fn share_transfer(&mut self, sender_id: &AccountId, receiver_id: &AccountId, amount: u128) {
+        let balance = self.shares.get(sender_id).expect(ERR13_LP_NOT_REGISTERED);
+        if let Some(new_balance) = balance.checked_sub(amount) {
+            self.shares.insert(sender_id, &new_balance);
+        } else {
+            env::panic(ERR34_INSUFFICIENT_LP_SHARES.as_bytes());
+        }
+        let balance_out = self
+            .shares
+            .get(receiver_id)
+            .expect(ERR13_LP_NOT_REGISTERED);
+        self.shares.insert(receiver_id, &(balance_out + amount));
+    }
+
     /// Returns total number of shares in this pool.
     pub fn share_total_balance(&self) -> Balance {
         self.shares_total_supply
@@ -92,68 +101,46 @@ impl SimplePool {
     }
 
     pub fn is_lp(&self, account_id: &AccountId) -> bool {
-        if self.shares.get(account_id).is_some() {
-            return true;
-        } else {
-            return false;
-        }
+        self.shares.get(account_id).is_some()
     }
 
     /// adds the amounts of tokens to liquidity pool and returns number of shares that this user receives.
     /// Updates amount to amount kept in the pool.
-    pub fn add_liquidity(&mut self, sender_id: &AccountId, amounts: &mut Vec<Balance>) -> Balance {
+    pub fn add_liquidity(&mut self, sender_id: &AccountId, amounts: &mut [Balance]) -> Balance {
         assert_eq!(
             amounts.len(),
             self.token_account_ids.len(),
             "ERR_WRONG_TOKEN_COUNT"
         );
+        self.update_oracle();
         let shares = if self.shares_total_supply > 0 {
             let mut fair_supply = U256::max_value();
-            for i in 0..self.token_account_ids.len() {
-                assert!(amounts[i] > 0, "{}", ERR31_ZERO_AMOUNT);
+            for (amount, pool_amount) in amounts.iter().zip(self.amounts.iter()) {
+                assert!(*amount > 0, "{}", ERR31_ZERO_AMOUNT);
                 fair_supply = min(
                     fair_supply,
-                    U256::from(amounts[i]) * U256::from(self.shares_total_supply) / self.amounts[i],
+                    U256::from(*amount) * U256::from(self.shares_total_supply) / *pool_amount,
                 );
             }
-            for i in 0..self.token_account_ids.len() {
-                let amount = (U256::from(self.amounts[i]) * fair_supply
+            for (amount, pool_amount) in amounts.iter_mut().zip(self.amounts.iter_mut()) {
+                let balanced = (U256::from(*pool_amount) * fair_supply
                     / U256::from(self.shares_total_supply))
                 .as_u128();
-                assert!(amount > 0, "{}", ERR31_ZERO_AMOUNT);
-                self.amounts[i] += amount;
-                amounts[i] = amount;
-                println!(
-                    "Amount[{}]: {}, Self amount[{}]: {}",
-                    i,
-                    amount.clone(),
-                    i,
-                    self.amounts[i].clone()
-                );
+                assert!(balanced > 0, "{}", ERR31_ZERO_AMOUNT);
+                *pool_amount += balanced;
+                *amount = balanced;
             }
             fair_supply.as_u128()
         } else {
-            for i in 0..self.token_account_ids.len() {
-                self.amounts[i] += amounts[i];
+            for (amount, pool_amount) in amounts.iter().zip(self.amounts.iter_mut()) {
+                assert!(*amount > 0, "{}", ERR65_INIT_TOKEN_BALANCE);
+                *pool_amount += *amount;
             }
             INIT_SHARES_SUPPLY
         };
-        self.mint_shares(&sender_id, shares);
+        self.mint_shares(sender_id, shares);
         assert!(shares > 0, "{}", ERR32_ZERO_SHARES);
 
-        env::log(
-            format!(
-                "Liquidity added {:?}, minted {} shares",
-                amounts
-                    .iter()
-                    .zip(self.token_account_ids.iter())
-                    .map(|(amount, token_id)| format!("{} {}", amount, token_id))
-                    .collect::<Vec<String>>(),
-                shares
-            )
-            .as_bytes(),
-        );
-
         // let shares = if self.shares_total_supply > 0 {
         //     let mut fair_supply = U256::max_value();
         //     for i in 0..self.token_account_ids.len() {
@@ -179,7 +166,7 @@ impl SimplePool {
             return;
         };
         self.shares_total_supply += shares;
-        add_to_collection(&mut self.shares, &account_id, shares);
+        add_to_collection(&mut self.shares, account_id, shares);
     }
 
     pub fn remove_liquidity(
@@ -193,41 +180,53 @@ impl SimplePool {
             self.token_account_ids.len(),
             "ERR_WRONG_TOKEN_COUNT"
         );
+        self.update_oracle();
         // check current shares in pool, must be greater than input "shares"
-        let prev_shares_amount = self.shares.get(&sender_id).expect("ERR_NO_SHARES");
+        let prev_shares_amount = self.shares.get(sender_id).expect("ERR_NO_SHARES");
         assert!(prev_shares_amount >= shares, "ERR_NOT_ENOUGH_SHARES");
         let mut amounts = vec![];
-        for i in 0..self.token_account_ids.len() {
-            let amount = (U256::from(self.amounts[i]) * U256::from(shares)
+        for (pool_amount, min_amount) in self.amounts.iter_mut().zip(min_amounts.iter()) {
+            let amount = (U256::from(*pool_amount) * U256::from(shares)
                 / U256::from(self.shares_total_supply))
             .as_u128();
-            assert!(amount >= min_amounts[i], "ERR_MIN_AMOUNT");
-            self.amounts[i] -= amount;
+            assert!(amount >= *min_amount, "ERR_MIN_AMOUNT");
+            *pool_amount -= amount;
             amounts.push(amount);
         }
         if prev_shares_amount == shares {
             // [AUDIT_13] Never unregister a LP when he removed all his liquidity.
-            self.shares.insert(&sender_id, &0);
+            self.shares.insert(sender_id, &0);
         } else {
             self.shares
-                .insert(&sender_id, &(prev_shares_amount - shares));
+                .insert(sender_id, &(prev_shares_amount - shares));
         }
-        env::log(
-            format!(
-                "{} shares of liquidity removed: receive back {:?}",
-                shares,
-                amounts
-                    .iter()
-                    .zip(self.token_account_ids.iter())
-                    .map(|(amount, token_id)| format!("{} {}", amount, token_id))
-                    .collect::<Vec<String>>(),
-            )
-            .as_bytes(),
-        );
         self.shares_total_supply -= shares;
         amounts
     }
 
+    /// Accumulates prices of the oracle up to now, must be called before `amounts` change.
+    pub fn update_oracle(&mut self) {
+        if let Some(oracle) = self.oracle.as_mut() {
+            oracle.update(&self.amounts);
+        }
+    }
+
+    /// Returns average price of `token_in` in `token_out` over last `window_sec` seconds,
+    /// in precision `PRICE_PRECISION`.
+    pub fn get_twap(&self, token_in: &AccountId, token_out: &AccountId, window_sec: u64) -> u128 {
+        self.oracle
+            .as_ref()
+            .unwrap_or_else(|| env::panic(ERR86_UNSUPPORTED_POOL_KIND.as_bytes()))
+            .get_twap(
+                &self.amounts,
+                self.token_index(token_in),
+                self.token_index(token_out),
+                window_sec
+                    .checked_mul(1_000_000_000)
+                    .expect(ERR90_TWAP_WINDOW_ILLEGAL),
+            )
+    }
+
     // Returns index of token in given pool
     fn token_index(&self, token_id: &AccountId) -> usize {
         self.token_account_ids
@@ -236,7 +235,8 @@ impl SimplePool {
             .expect("ERR_MISSING_TOKEN")
     }
 
-    /// Returns number of tokens in income, given amount.
+    /// Returns number of tokens in income required to receive given amount of outcome, fee included.
+    /// Inverse of `internal_get_return`, rounded up so the swap never returns less than `amount_out`.
     /// Tokens are provided as indexes into token list for given pool.
     fn internal_get_income(
         &self,
@@ -248,14 +248,16 @@ impl SimplePool {
         let out_balance = U256::from(self.amounts[token_out]);
         assert!(
             in_balance > U256::zero()
-                && out_balance > U256::zero()
+                && out_balance > U256::from(amount_out)
                 && token_in != token_out
                 && amount_out > 0,
             "ERR_INVALID"
         );
 
-        (in_balance - (in_balance * out_balance / out_balance - amount_out))
-            .as_u128()
+        let numerator = in_balance * U256::from(amount_out) * U256::from(FEE_DIVISOR);
+        let denominator =
+            (out_balance - U256::from(amount_out)) * U256::from(FEE_DIVISOR - self.total_fee);
+        (numerator / denominator + U256::one()).as_u128()
     }
 
     /// Returns number of tokens in outcome, given amount.
@@ -307,6 +309,20 @@ impl SimplePool {
         )
     }
 
+    /// Returns None instead of failing for empty reserves.
+    pub fn try_get_return(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> Option<Balance> {
+        let (in_idx, out_idx) = (self.token_index(token_in), self.token_index(token_out));
+        if self.amounts[in_idx] == 0 || self.amounts[out_idx] == 0 {
+            return None;
+        }
+        Some(self.internal_get_return(in_idx, amount_in, out_idx))
+    }
+
     pub fn get_fee(&self) -> u32 {
         self.total_fee
     }
@@ -318,6 +334,7 @@ impl SimplePool {
 
     /// Swap `token_amount_in` of `token_in` token into `token_out` and return how much was received.
     /// Assuming that `token_amount_in` was already received from `sender_id`.
+    /// Any two tokens of the pool can be traded, priced by the constant product of their balances.
     pub fn swap(
         &mut self,
         token_in: &AccountId,
@@ -331,13 +348,7 @@ impl SimplePool {
         let out_idx = self.token_index(token_out);
         let amount_out = self.internal_get_return(in_idx, amount_in, out_idx);
         assert!(amount_out >= min_amount_out, "ERR_MIN_AMOUNT");
-        env::log(
-            format!(
-                "Swapped {} {} for {} {}",
-                amount_in, token_in, amount_out, token_out
-            )
-            .as_bytes(),
-        );
+        self.update_oracle();
 
         let prev_invariant =
             uint_sqrt(U256::from(self.amounts[in_idx]) * U256::from(self.amounts[out_idx]));
@@ -369,16 +380,6 @@ impl SimplePool {
                 self.mint_shares(referral_id, (numerator / denominator).as_u128());
             }
         }
-        // if self.total_fee > 0 {
-        //     let shares = self.shares.to_vec();
-        //     let first_provider = self.first_provider.as_ref().unwrap();
-        //     let first_provider_share = self.shares.get(&first_provider).unwrap();
-        //     self.shares.insert(&first_provider, &(first_provider_share + first_provider_earn));
-        //     for i in 0..shares.len() {
-        //         add_to_collection(&mut self.shares, &shares[i].0, shares[i].1 + other_provider_earn);
-        //     }
-        // }
-
         // Keeping track of volume per each input traded separately.
         // Reported volume with fees will be sum of `input`, without fees will be sum of `output`.
         self.volumes[in_idx].input.0 += amount_in;
@@ -388,16 +389,27 @@ impl SimplePool {
     }
 
     pub fn predict_remove_liquidity(&self, shares: Balance) -> Vec<u128> {
-        let num_tokens = self.token_account_ids.len();
-        let mut result = vec![0u128; num_tokens];
-        for i in 0..num_tokens {
-            result[i] = U256::from(self.amounts[i])
-                .checked_mul(shares.into())
-                .unwrap()
-                .checked_div(self.shares_total_supply.into())
-                .unwrap_or_default()
-                .as_u128();
+        self.amounts
+            .iter()
+            .map(|amount| {
+                U256::from(*amount)
+                    .checked_mul(shares.into())
+                    .unwrap()
+                    .checked_div(self.shares_total_supply.into())
+                    .unwrap_or_default()
+                    .as_u128()
+            })
+            .collect()
+    }
+
+    /// Returns value of given shares in units of the first token.
+    /// Constant product keeps equal value in every token, so the pool is worth n times the first balance.
+    pub fn get_shares_value(&self, shares: Balance) -> u128 {
+        if self.shares_total_supply == 0 {
+            return 0;
         }
-        result
+        (U256::from(self.amounts[0]) * U256::from(self.amounts.len()) * U256::from(shares)
+            / U256::from(self.shares_total_supply))
+        .as_u128()
     }
 }
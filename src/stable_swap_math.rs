@@ -0,0 +1,301 @@
+//! Curve StableSwap invariant math.
+//! All balances given to this module must already be normalized to `TARGET_DECIMAL`.
+
+use near_sdk::{Balance, Timestamp};
+
+use crate::utils::{FEE_DIVISOR, U256};
+
+/// Number of decimals every token balance is normalized to before running the invariant.
+pub const TARGET_DECIMAL: u8 = 24;
+pub const MIN_DECIMAL: u8 = 1;
+pub const MAX_DECIMAL: u8 = 24;
+
+pub const MIN_AMP: u128 = 1;
+pub const MAX_AMP: u128 = 1_000_000;
+/// Max ratio between current and target amp in one ramping.
+pub const MAX_AMP_CHANGE: u128 = 10;
+/// Min duration (in nanoseconds) of a ramping and of the lock after it started.
+pub const MIN_RAMP_DURATION: Timestamp = 86400 * 1_000_000_000;
+
+/// Max number of newton iterations before giving up on convergence.
+const MAX_ITERATIONS: usize = 256;
+
+/// Amplification factor with optional linear ramping between two timestamps.
+pub struct StableSwap {
+    init_amp_factor: u128,
+    target_amp_factor: u128,
+    current_ts: Timestamp,
+    start_ramp_ts: Timestamp,
+    stop_ramp_ts: Timestamp,
+}
+
+impl StableSwap {
+    pub fn new(
+        init_amp_factor: u128,
+        target_amp_factor: u128,
+        current_ts: Timestamp,
+        start_ramp_ts: Timestamp,
+        stop_ramp_ts: Timestamp,
+    ) -> Self {
+        Self {
+            init_amp_factor,
+            target_amp_factor,
+            current_ts,
+            start_ramp_ts,
+            stop_ramp_ts,
+        }
+    }
+
+    /// Returns amp at current timestamp, interpolated if ramping is in progress.
+    pub fn compute_amp_factor(&self) -> Option<u128> {
+        if self.current_ts < self.stop_ramp_ts {
+            let time_range = self.stop_ramp_ts.checked_sub(self.start_ramp_ts)?;
+            let time_delta = self.current_ts.checked_sub(self.start_ramp_ts)?;
+            if self.target_amp_factor >= self.init_amp_factor {
+                let amp_range = self.target_amp_factor - self.init_amp_factor;
+                let amp_delta = amp_range * time_delta as u128 / time_range as u128;
+                self.init_amp_factor.checked_add(amp_delta)
+            } else {
+                let amp_range = self.init_amp_factor - self.target_amp_factor;
+                let amp_delta = amp_range * time_delta as u128 / time_range as u128;
+                self.init_amp_factor.checked_sub(amp_delta)
+            }
+        } else {
+            Some(self.target_amp_factor)
+        }
+    }
+
+    /// Computes invariant D for given normalized balances.
+    pub fn compute_d(&self, c_amounts: &[Balance]) -> Option<U256> {
+        let n_coins = U256::from(c_amounts.len());
+        let sum_x = c_amounts
+            .iter()
+            .fold(U256::zero(), |acc, x| acc + U256::from(*x));
+        if sum_x.is_zero() {
+            return Some(U256::zero());
+        }
+        let amp_factor = U256::from(self.compute_amp_factor()?);
+        let ann = amp_factor.checked_mul(n_coins)?;
+        let mut d = sum_x;
+        for _ in 0..MAX_ITERATIONS {
+            let mut d_prod = d;
+            for c_amount in c_amounts {
+                d_prod = d_prod
+                    .checked_mul(d)?
+                    .checked_div(U256::from(*c_amount) * n_coins)?;
+            }
+            let d_prev = d;
+            // d = (ann * sum_x + d_prod * n) * d / ((ann - 1) * d + (n + 1) * d_prod)
+            let numerator = ann
+                .checked_mul(sum_x)?
+                .checked_add(d_prod.checked_mul(n_coins)?)?
+                .checked_mul(d)?;
+            let denominator = (ann - U256::one())
+                .checked_mul(d)?
+                .checked_add((n_coins + U256::one()).checked_mul(d_prod)?)?;
+            d = numerator.checked_div(denominator)?;
+            if d > d_prev {
+                if d - d_prev <= U256::one() {
+                    return Some(d);
+                }
+            } else if d_prev - d <= U256::one() {
+                return Some(d);
+            }
+        }
+        None
+    }
+
+    /// Computes new balance of token `index_y` which keeps invariant `d`,
+    /// given all other normalized balances in `c_amounts` (value at `index_y` is ignored).
+    pub fn compute_y(&self, c_amounts: &[Balance], index_y: usize, d: U256) -> Option<U256> {
+        let n_coins = U256::from(c_amounts.len());
+        let amp_factor = U256::from(self.compute_amp_factor()?);
+        let ann = amp_factor.checked_mul(n_coins)?;
+
+        let mut c = d;
+        let mut sum_x = U256::zero();
+        for (idx, c_amount) in c_amounts.iter().enumerate() {
+            if idx != index_y {
+                sum_x = sum_x.checked_add(U256::from(*c_amount))?;
+                c = c
+                    .checked_mul(d)?
+                    .checked_div(U256::from(*c_amount) * n_coins)?;
+            }
+        }
+        c = c.checked_mul(d)?.checked_div(ann.checked_mul(n_coins)?)?;
+        let b = sum_x.checked_add(d.checked_div(ann)?)?;
+
+        // y = (y^2 + c) / (2y + b - d)
+        let mut y = d;
+        for _ in 0..MAX_ITERATIONS {
+            let y_prev = y;
+            let numerator = y.checked_mul(y)?.checked_add(c)?;
+            let denominator = y
+                .checked_mul(U256::from(2))?
+                .checked_add(b)?
+                .checked_sub(d)?;
+            y = numerator.checked_div(denominator)?;
+            if y > y_prev {
+                if y - y_prev <= U256::one() {
+                    return Some(y);
+                }
+            } else if y_prev - y <= U256::one() {
+                return Some(y);
+            }
+        }
+        None
+    }
+
+    /// Returns normalized amount of `token_out` received for `amount_in` of `token_in`,
+    /// and the fee part (in `token_out`) kept by the pool.
+    pub fn swap_to(
+        &self,
+        token_in: usize,
+        amount_in: Balance,
+        token_out: usize,
+        c_amounts: &[Balance],
+        total_fee: u32,
+    ) -> Option<(Balance, Balance)> {
+        let d = self.compute_d(c_amounts)?;
+        let mut new_amounts = c_amounts.to_vec();
+        new_amounts[token_in] = new_amounts[token_in].checked_add(amount_in)?;
+        let y = self.compute_y(&new_amounts, token_out, d)?;
+        // Subtract one to round in favour of the pool.
+        let dy = U256::from(c_amounts[token_out])
+            .checked_sub(y)?
+            .checked_sub(U256::one())?;
+        let fee = dy * U256::from(total_fee) / U256::from(FEE_DIVISOR);
+        Some(((dy - fee).as_u128(), fee.as_u128()))
+    }
+
+    /// Returns normalized amount of `token_in` required to receive `amount_out` of `token_out`.
+    pub fn swap_from(
+        &self,
+        token_in: usize,
+        token_out: usize,
+        amount_out: Balance,
+        c_amounts: &[Balance],
+        total_fee: u32,
+    ) -> Option<Balance> {
+        let d = self.compute_d(c_amounts)?;
+        // Gross up the output so that it still covers `amount_out` once the fee is taken.
+        let fee_rate = U256::from(FEE_DIVISOR - total_fee);
+        let dy = (U256::from(amount_out) * U256::from(FEE_DIVISOR) + fee_rate - U256::one())
+            / fee_rate
+            + U256::one();
+        let mut new_amounts = c_amounts.to_vec();
+        new_amounts[token_out] = U256::from(c_amounts[token_out]).checked_sub(dy)?.as_u128();
+        if new_amounts[token_out] == 0 {
+            return None;
+        }
+        let x = self.compute_y(&new_amounts, token_in, d)?;
+        Some((x.checked_sub(U256::from(c_amounts[token_in]))? + U256::one()).as_u128())
+    }
+
+    /// Returns number of shares to mint for depositing `deposit_c_amounts` into a pool
+    /// that holds `old_c_amounts` and `pool_token_supply` shares.
+    /// An imbalance fee is charged on the part of the deposit that deviates from the pool ratio.
+    pub fn compute_lp_amount_for_deposit(
+        &self,
+        deposit_c_amounts: &[Balance],
+        old_c_amounts: &[Balance],
+        pool_token_supply: Balance,
+        total_fee: u32,
+    ) -> Option<Balance> {
+        let n_coins = old_c_amounts.len();
+        let new_c_amounts = old_c_amounts
+            .iter()
+            .zip(deposit_c_amounts.iter())
+            .map(|(old, deposit)| old.checked_add(*deposit))
+            .collect::<Option<Vec<Balance>>>()?;
+        let d_1 = self.compute_d(&new_c_amounts)?;
+        if pool_token_supply == 0 {
+            return Some(d_1.as_u128());
+        }
+        let d_0 = self.compute_d(old_c_amounts)?;
+        if d_1 <= d_0 {
+            return None;
+        }
+
+        // Curve imbalance fee: fee * n / (4 * (n - 1)) per token.
+        let fee_per_token =
+            U256::from(total_fee) * U256::from(n_coins) / U256::from(4 * (n_coins - 1));
+        let mut adjusted_amounts = Vec::with_capacity(n_coins);
+        for (old, new) in old_c_amounts.iter().zip(new_c_amounts.iter()) {
+            let ideal = d_1 * U256::from(*old) / d_0;
+            let new = U256::from(*new);
+            let difference = if ideal > new {
+                ideal - new
+            } else {
+                new - ideal
+            };
+            let fee = fee_per_token * difference / U256::from(FEE_DIVISOR);
+            adjusted_amounts.push(new.checked_sub(fee)?.as_u128());
+        }
+        let d_2 = self.compute_d(&adjusted_amounts)?;
+        if d_2 <= d_0 {
+            return None;
+        }
+        Some((U256::from(pool_token_supply) * (d_2 - d_0) / d_0).as_u128())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ONE: Balance = 1_000_000_000_000_000_000_000_000;
+
+    fn stable_swap(amp: u128) -> StableSwap {
+        StableSwap::new(amp, amp, 0, 0, 0)
+    }
+
+    #[test]
+    fn test_compute_d_balanced_pool() {
+        let swap = stable_swap(100);
+        let d = swap.compute_d(&[100 * ONE, 100 * ONE, 100 * ONE]).unwrap();
+        assert_eq!(d, U256::from(300 * ONE));
+    }
+
+    #[test]
+    fn test_swap_low_slippage() {
+        let swap = stable_swap(100);
+        let (amount_out, fee) = swap
+            .swap_to(0, ONE, 1, &[1000 * ONE, 1000 * ONE], 0)
+            .unwrap();
+        assert_eq!(fee, 0);
+        // A constant product pool would return ~0.999 here.
+        assert!(amount_out > ONE * 9999 / 10000 && amount_out < ONE);
+    }
+
+    #[test]
+    fn test_swap_from_covers_swap_to() {
+        let swap = stable_swap(200);
+        let c_amounts = [500 * ONE, 1500 * ONE];
+        let amount_in = swap.swap_from(0, 1, 10 * ONE, &c_amounts, 25).unwrap();
+        let (amount_out, _) = swap.swap_to(0, amount_in, 1, &c_amounts, 25).unwrap();
+        assert!(amount_out >= 10 * ONE);
+    }
+
+    #[test]
+    fn test_amp_ramping() {
+        let swap = StableSwap::new(100, 200, 50, 0, 100);
+        assert_eq!(swap.compute_amp_factor(), Some(150));
+        let swap = StableSwap::new(200, 100, 150, 0, 100);
+        assert_eq!(swap.compute_amp_factor(), Some(100));
+    }
+
+    #[test]
+    fn test_imbalanced_deposit_is_charged() {
+        let swap = stable_swap(100);
+        let old = [1000 * ONE, 1000 * ONE];
+        let supply = 2000 * ONE;
+        let balanced = swap
+            .compute_lp_amount_for_deposit(&[10 * ONE, 10 * ONE], &old, supply, 30)
+            .unwrap();
+        let imbalanced = swap
+            .compute_lp_amount_for_deposit(&[20 * ONE, 0], &old, supply, 30)
+            .unwrap();
+        assert!(balanced > imbalanced);
+    }
+}
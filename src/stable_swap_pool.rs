@@ -0,0 +1,478 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::UnorderedMap;
+use near_sdk::json_types::ValidAccountId;
+use near_sdk::{env, AccountId, Balance, Timestamp};
+
+use crate::admin_fee::AdminFees;
+use crate::errors::*;
+use crate::stable_swap_math::{
+    StableSwap, MAX_AMP, MAX_AMP_CHANGE, MAX_DECIMAL, MIN_AMP, MIN_DECIMAL, MIN_RAMP_DURATION,
+    TARGET_DECIMAL,
+};
+use crate::utils::{
+    add_to_collection, SwapVolume, FEE_DIVISOR, MAX_NUM_TOKENS, MIN_NUM_TOKENS, U256,
+};
+use crate::StorageKey;
+
+/// Minimal normalized balance of each token that has to stay in the pool after a swap.
+pub const MIN_RESERVE: Balance = 1_000_000_000_000_000_000_000;
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct StableSwapPool {
+    /// List of tokens in the pool.
+    pub token_account_ids: Vec<AccountId>,
+    /// Decimals of each token in the pool.
+    pub token_decimals: Vec<u8>,
+    /// How much of each token this pool has.
+    pub amounts: Vec<Balance>,
+    /// Volumes accumulated by this pool.
+    pub volumes: Vec<SwapVolume>,
+    /// Fee charged for swap (gets divided by FEE_DIVISOR).
+    pub total_fee: u32,
+    /// Shares of the pool by liquidity providers.
+    pub shares: UnorderedMap<AccountId, Balance>,
+    /// Total number of shares.
+    pub shares_total_supply: Balance,
+    /// Amp factor at the start of the current ramping.
+    pub init_amp_factor: u128,
+    /// Amp factor at the end of the current ramping.
+    pub target_amp_factor: u128,
+    /// Timestamp the current ramping started at.
+    pub init_amp_time: Timestamp,
+    /// Timestamp the current ramping stops at.
+    pub stop_amp_time: Timestamp,
+}
+
+impl StableSwapPool {
+    pub fn new(
+        id: u32,
+        token_account_ids: Vec<ValidAccountId>,
+        token_decimals: Vec<u8>,
+        amp_factor: u128,
+        total_fee: u32,
+    ) -> Self {
+        assert!(
+            (MIN_AMP..=MAX_AMP).contains(&amp_factor),
+            "{}",
+            ERR61_AMP_ILLEGAL
+        );
+        assert!(total_fee < FEE_DIVISOR, "{}", ERR62_FEE_ILLEGAL);
+        assert!(
+            (MIN_NUM_TOKENS..=MAX_NUM_TOKENS).contains(&token_account_ids.len()),
+            "{}",
+            ERR64_TOKENS_COUNT_ILLEGAL
+        );
+        assert_eq!(
+            token_decimals.len(),
+            token_account_ids.len(),
+            "{}",
+            ERR60_DECIMAL_ILLEGAL
+        );
+        for decimal in token_decimals.iter() {
+            assert!(
+                (MIN_DECIMAL..=MAX_DECIMAL).contains(decimal),
+                "{}",
+                ERR60_DECIMAL_ILLEGAL
+            );
+        }
+        Self {
+            token_account_ids: token_account_ids.iter().map(|a| a.clone().into()).collect(),
+            token_decimals,
+            amounts: vec![0u128; token_account_ids.len()],
+            volumes: vec![SwapVolume::default(); token_account_ids.len()],
+            total_fee,
+            shares: UnorderedMap::new(StorageKey::Shares { pool_id: id }),
+            shares_total_supply: 0,
+            init_amp_factor: amp_factor,
+            target_amp_factor: amp_factor,
+            init_amp_time: 0,
+            stop_amp_time: 0,
+        }
+    }
+
+    /// Register given account with 0 balance in shares.
+    /// Storage payment should be checked by caller.
+    pub fn share_register(&mut self, account_id: &AccountId) {
+        if self.shares.get(account_id).is_some() {
+            env::panic(ERR14_LP_ALREADY_REGISTERED.as_bytes());
+        }
+        self.shares.insert(account_id, &0);
+    }
+
+    /// Returns balance of shares for given user.
+    pub fn share_balance_of(&self, account_id: &AccountId) -> Balance {
+        self.shares.get(account_id).unwrap_or_default()
+    }
+
+    /// Transfers shares from predecessor to receiver.
+    /// Receiver has to be registered, so no storage is allocated here.
+    pub fn share_transfer(&mut self, sender_id: &AccountId, receiver_id: &AccountId, amount: u128) {
+        let balance = self.shares.get(sender_id).expect(ERR13_LP_NOT_REGISTERED);
+        if let Some(new_balance) = balance.checked_sub(amount) {
+            self.shares.insert(sender_id, &new_balance);
+        } else {
+            env::panic(ERR34_INSUFFICIENT_LP_SHARES.as_bytes());
+        }
+        let balance_out = self
+            .shares
+            .get(receiver_id)
+            .expect(ERR13_LP_NOT_REGISTERED);
+        self.shares.insert(receiver_id, &(balance_out + amount));
+    }
+
+    /// Returns total number of shares in this pool.
+    pub fn share_total_balance(&self) -> Balance {
+        self.shares_total_supply
+    }
+
+    /// Returns list of tokens in this pool.
+    pub fn tokens(&self) -> &[AccountId] {
+        &self.token_account_ids
+    }
+
+    pub fn is_lp(&self, account_id: &AccountId) -> bool {
+        self.shares.get(account_id).is_some()
+    }
+
+    pub fn get_fee(&self) -> u32 {
+        self.total_fee
+    }
+
+    /// Returns volumes of the given pool.
+    pub fn get_volumes(&self) -> Vec<SwapVolume> {
+        self.volumes.clone()
+    }
+
+    /// Returns current amp factor, taking ongoing ramping into account.
+    pub fn get_amp(&self) -> u64 {
+        self.internal_stable_swap()
+            .compute_amp_factor()
+            .expect(ERR66_INVARIANT_CALC_ERR) as u64
+    }
+
+    /// Returns token balances normalized to `TARGET_DECIMAL`.
+    pub fn get_c_amounts(&self) -> Vec<Balance> {
+        self.amounts
+            .iter()
+            .enumerate()
+            .map(|(idx, amount)| self.to_c_amount(idx, *amount))
+            .collect()
+    }
+
+    fn internal_stable_swap(&self) -> StableSwap {
+        StableSwap::new(
+            self.init_amp_factor,
+            self.target_amp_factor,
+            env::block_timestamp(),
+            self.init_amp_time,
+            self.stop_amp_time,
+        )
+    }
+
+    /// Multiplier to normalize given token to `TARGET_DECIMAL`.
+    fn rate(&self, token_idx: usize) -> Balance {
+        10u128.pow((TARGET_DECIMAL - self.token_decimals[token_idx]) as u32)
+    }
+
+    fn to_c_amount(&self, token_idx: usize, amount: Balance) -> Balance {
+        amount
+            .checked_mul(self.rate(token_idx))
+            .expect(ERR77_AMOUNT_OVERFLOW)
+    }
+
+    // Returns index of token in given pool
+    fn token_index(&self, token_id: &AccountId) -> usize {
+        self.token_account_ids
+            .iter()
+            .position(|id| id == token_id)
+            .expect(ERR63_MISSING_TOKEN)
+    }
+
+    pub fn mint_shares(&mut self, account_id: &AccountId, shares: Balance) {
+        if shares == 0 {
+            return;
+        }
+        self.shares_total_supply += shares;
+        add_to_collection(&mut self.shares, account_id, shares);
+    }
+
+    /// Adds given amounts of tokens to the pool and returns number of minted shares.
+    /// Unlike simple pool, all amounts are taken and an imbalance fee is charged instead.
+    pub fn add_liquidity(
+        &mut self,
+        sender_id: &AccountId,
+        amounts: &[Balance],
+        min_shares: Balance,
+    ) -> Balance {
+        assert_eq!(
+            amounts.len(),
+            self.token_account_ids.len(),
+            "ERR_WRONG_TOKEN_COUNT"
+        );
+        let deposit_c_amounts = amounts
+            .iter()
+            .enumerate()
+            .map(|(idx, amount)| self.to_c_amount(idx, *amount))
+            .collect::<Vec<Balance>>();
+        if self.shares_total_supply == 0 {
+            for amount in amounts.iter() {
+                assert!(*amount > 0, "{}", ERR65_INIT_TOKEN_BALANCE);
+            }
+        }
+        let shares = self
+            .internal_stable_swap()
+            .compute_lp_amount_for_deposit(
+                &deposit_c_amounts,
+                &self.get_c_amounts(),
+                self.shares_total_supply,
+                self.total_fee,
+            )
+            .expect(ERR67_LPSHARE_CALC_ERR);
+        assert!(shares > 0, "{}", ERR32_ZERO_SHARES);
+        assert!(shares >= min_shares, "{}", ERR68_SLIPPAGE);
+
+        for (pool_amount, amount) in self.amounts.iter_mut().zip(amounts.iter()) {
+            *pool_amount += *amount;
+        }
+        self.mint_shares(sender_id, shares);
+
+        shares
+    }
+
+    /// Removes given number of shares from the pool, returning proportional amounts of each token.
+    pub fn remove_liquidity(
+        &mut self,
+        sender_id: &AccountId,
+        shares: Balance,
+        min_amounts: Vec<Balance>,
+    ) -> Vec<Balance> {
+        assert_eq!(
+            min_amounts.len(),
+            self.token_account_ids.len(),
+            "ERR_WRONG_TOKEN_COUNT"
+        );
+        let prev_shares_amount = self.shares.get(sender_id).expect("ERR_NO_SHARES");
+        assert!(prev_shares_amount >= shares, "ERR_NOT_ENOUGH_SHARES");
+        let amounts = self.predict_remove_liquidity(shares);
+        for ((pool_amount, amount), min_amount) in self
+            .amounts
+            .iter_mut()
+            .zip(amounts.iter())
+            .zip(min_amounts.iter())
+        {
+            assert!(amount >= min_amount, "{}", ERR68_SLIPPAGE);
+            *pool_amount -= *amount;
+        }
+        // Never unregister a LP when he removed all his liquidity.
+        self.shares
+            .insert(sender_id, &(prev_shares_amount - shares));
+        self.shares_total_supply -= shares;
+        amounts
+    }
+
+    pub fn predict_remove_liquidity(&self, shares: Balance) -> Vec<Balance> {
+        self.amounts
+            .iter()
+            .map(|amount| {
+                U256::from(*amount)
+                    .checked_mul(shares.into())
+                    .unwrap()
+                    .checked_div(self.shares_total_supply.into())
+                    .unwrap_or_default()
+                    .as_u128()
+            })
+            .collect()
+    }
+
+    /// Returns value of given shares in units of the first token.
+    /// Invariant is the pool value in `TARGET_DECIMAL`, converted back to decimals of the first token.
+    pub fn get_shares_value(&self, shares: Balance) -> u128 {
+        if self.shares_total_supply == 0 {
+            return 0;
+        }
+        let d = self
+            .internal_stable_swap()
+            .compute_d(&self.get_c_amounts())
+            .expect(ERR66_INVARIANT_CALC_ERR);
+        (d * U256::from(shares) / (U256::from(self.shares_total_supply) * U256::from(self.rate(0))))
+            .as_u128()
+    }
+
+    /// Returns number of tokens in outcome and fee kept by the pool (both normalized).
+    fn internal_get_return(
+        &self,
+        token_in: usize,
+        amount_in: Balance,
+        token_out: usize,
+    ) -> (Balance, Balance) {
+        assert_ne!(token_in, token_out, "{}", ERR71_SWAP_DUP_TOKENS);
+        assert!(amount_in > 0, "ERR_INVALID");
+        self.internal_stable_swap()
+            .swap_to(
+                token_in,
+                self.to_c_amount(token_in, amount_in),
+                token_out,
+                &self.get_c_amounts(),
+                self.total_fee,
+            )
+            .expect(ERR70_SWAP_OUT_CALC_ERR)
+    }
+
+    pub fn get_return(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> Balance {
+        let out_idx = self.token_index(token_out);
+        let (c_amount_out, _) =
+            self.internal_get_return(self.token_index(token_in), amount_in, out_idx);
+        c_amount_out / self.rate(out_idx)
+    }
+
+    /// Returns None instead of failing for empty reserves or amounts the invariant can't be solved for.
+    pub fn try_get_return(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> Option<Balance> {
+        if self.amounts.contains(&0) {
+            return None;
+        }
+        let (in_idx, out_idx) = (self.token_index(token_in), self.token_index(token_out));
+        let (c_amount_out, _) = self.internal_stable_swap().swap_to(
+            in_idx,
+            amount_in.checked_mul(self.rate(in_idx))?,
+            out_idx,
+            &self.get_c_amounts(),
+            self.total_fee,
+        )?;
+        Some(c_amount_out / self.rate(out_idx))
+    }
+
+    pub fn get_income(
+        &self,
+        token_in: &AccountId,
+        token_out: &AccountId,
+        amount_out: Balance,
+    ) -> Balance {
+        let in_idx = self.token_index(token_in);
+        let out_idx = self.token_index(token_out);
+        assert_ne!(in_idx, out_idx, "{}", ERR71_SWAP_DUP_TOKENS);
+        assert!(amount_out > 0, "ERR_INVALID");
+        let c_amount_in = self
+            .internal_stable_swap()
+            .swap_from(
+                in_idx,
+                out_idx,
+                self.to_c_amount(out_idx, amount_out),
+                &self.get_c_amounts(),
+                self.total_fee,
+            )
+            .expect(ERR70_SWAP_OUT_CALC_ERR);
+        let rate = self.rate(in_idx);
+        c_amount_in.div_ceil(rate)
+    }
+
+    /// Swap `amount_in` of `token_in` token into `token_out` and return how much was received.
+    /// Assuming that `amount_in` was already received from `sender_id`.
+    pub fn swap(
+        &mut self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+        min_amount_out: Balance,
+        admin_fee: &AdminFees,
+    ) -> Balance {
+        let in_idx = self.token_index(token_in);
+        let out_idx = self.token_index(token_out);
+        let (c_amount_out, _) = self.internal_get_return(in_idx, amount_in, out_idx);
+        let amount_out = c_amount_out / self.rate(out_idx);
+        assert!(amount_out >= min_amount_out, "{}", ERR68_SLIPPAGE);
+
+        let stable_swap = self.internal_stable_swap();
+        let prev_invariant = stable_swap
+            .compute_d(&self.get_c_amounts())
+            .expect(ERR66_INVARIANT_CALC_ERR);
+
+        self.amounts[in_idx] += amount_in;
+        self.amounts[out_idx] -= amount_out;
+        assert!(
+            self.to_c_amount(out_idx, self.amounts[out_idx]) >= MIN_RESERVE,
+            "{}",
+            ERR69_MIN_RESERVE
+        );
+
+        let new_invariant = stable_swap
+            .compute_d(&self.get_c_amounts())
+            .expect(ERR66_INVARIANT_CALC_ERR);
+        // Invariant can not reduce (otherwise losing balance of the pool and something it broken).
+        assert!(new_invariant >= prev_invariant, "ERR_INVARIANT");
+        let numerator = (new_invariant - prev_invariant) * U256::from(self.shares_total_supply);
+
+        // Allocates exchange fee as fraction of total fee by issuing LP shares proportionally
+        if admin_fee.exchange_fee > 0 && numerator > U256::zero() {
+            let denominator = new_invariant * FEE_DIVISOR / admin_fee.exchange_fee;
+            self.mint_shares(&admin_fee.exchange_id, (numerator / denominator).as_u128());
+        }
+
+        // If there is referral provided and the account already registered LP, allocate it * of LP rewards.
+        if let Some(referral_id) = &admin_fee.referral_id {
+            if admin_fee.referral_fee > 0
+                && numerator > U256::zero()
+                && self.shares.get(referral_id).is_some()
+            {
+                let denominator = new_invariant * FEE_DIVISOR / admin_fee.referral_fee;
+                self.mint_shares(referral_id, (numerator / denominator).as_u128());
+            }
+        }
+
+        self.volumes[in_idx].input.0 += amount_in;
+        self.volumes[in_idx].output.0 += amount_out;
+
+        amount_out
+    }
+
+    /// Starts linear ramping of amp factor to `future_amp_factor` until `future_amp_time`.
+    pub fn ramp_amplification(&mut self, future_amp_factor: u128, future_amp_time: Timestamp) {
+        let current_time = env::block_timestamp();
+        assert!(
+            current_time >= self.init_amp_time + MIN_RAMP_DURATION,
+            "{}",
+            ERR81_AMP_IN_LOCK
+        );
+        assert!(
+            future_amp_time >= current_time + MIN_RAMP_DURATION,
+            "{}",
+            ERR82_INSUFFICIENT_RAMP_TIME
+        );
+        assert!(
+            (MIN_AMP..=MAX_AMP).contains(&future_amp_factor),
+            "{}",
+            ERR83_INVALID_AMP_FACTOR
+        );
+        let amp_factor = self.get_amp() as u128;
+        assert!(
+            (future_amp_factor >= amp_factor && future_amp_factor <= amp_factor * MAX_AMP_CHANGE)
+                || (future_amp_factor < amp_factor
+                    && future_amp_factor * MAX_AMP_CHANGE >= amp_factor),
+            "{}",
+            ERR84_AMP_LARGE_CHANGE
+        );
+        self.init_amp_factor = amp_factor;
+        self.init_amp_time = current_time;
+        self.target_amp_factor = future_amp_factor;
+        self.stop_amp_time = future_amp_time;
+    }
+
+    /// Stops ongoing ramping, fixing amp factor at its current value.
+    pub fn stop_ramp_amplification(&mut self) {
+        let amp_factor = self.get_amp() as u128;
+        let current_time = env::block_timestamp();
+        self.init_amp_factor = amp_factor;
+        self.target_amp_factor = amp_factor;
+        self.init_amp_time = current_time;
+        self.stop_amp_time = current_time;
+    }
+}
@@ -16,10 +16,10 @@ impl StorageManagement for Contract {
         let amount = env::attached_deposit();
         let account_id = account_id
             .map(|a| a.into())
-            .unwrap_or_else(|| env::predecessor_account_id());
+            .unwrap_or_else(env::predecessor_account_id);
         let registration_only = registration_only.unwrap_or(false);
         let min_balance = self.storage_balance_bounds().min.0;
-        let already_registered = self.accounts.contains_key(&account_id);
+        let already_registered = self.internal_is_registered(&account_id);
         if amount < min_balance && !already_registered {
             env::panic(b"ERR_DEPOSIT_LESS_THAN_MIN_STORAGE");
         }
@@ -66,7 +66,7 @@ impl StorageManagement for Contract {
                 account_deposit.tokens.is_empty(),
                 "ERR_STORAGE_UNREGISTER_TOKENS_NOT_EMPTY"
             );
-            self.accounts.remove(&account_id);
+            self.internal_remove_account(&account_id);
             Promise::new(account_id.clone()).transfer(account_deposit.near_amount);
             true
         } else {
@@ -0,0 +1,288 @@
+//! Time-locked admin actions.
+//!
+//! Changes of fees, ownership, guardians and wNEAR token, and retrieval of unmanaged tokens don't take effect
+//! immediately. Owner proposes the action, which can be executed by owner once `timelock_delay_sec`
+//! passed since the proposal, so LPs get notice through the views and events. Until executed,
+//! guardians can veto the proposal and owner can cancel it. Removal of guardians can't be vetoed,
+//! so guardians can't keep themselves or each other in place.
+//! The delay itself is changed by a proposal, under the current delay.
+
+use near_contract_standards::fungible_token::core_impl::ext_fungible_token;
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::PromiseOrValue;
+
+use crate::utils::{now_sec, FEE_DIVISOR, GAS_FOR_BASIC_OP};
+use crate::*;
+
+/// Delay (in seconds) between proposal and execution of admin actions at initialization.
+pub const DEFAULT_TIMELOCK_DELAY_SEC: u64 = 24 * 3600;
+/// Max delay, so proposals can't be locked forever.
+pub const MAX_TIMELOCK_DELAY_SEC: u64 = 30 * 24 * 3600;
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub enum AdminAction {
+    ModifyAdminFee {
+        exchange_fee: u32,
+        referral_fee: u32,
+    },
+    SetOwner {
+        owner_id: ValidAccountId,
+    },
+    ExtendGuardians {
+        guardians: Vec<ValidAccountId>,
+    },
+    RemoveGuardians {
+        guardians: Vec<ValidAccountId>,
+    },
+    /// Retrieves NEP-141 tokens that are not managed by contract to owner.
+    /// Caution: Must check that `amount <= total_amount_in_account - amount_managed_by_contract` before executing !!!
+    RetrieveUnmanagedToken {
+        token_id: ValidAccountId,
+        amount: U128,
+    },
+    SetTimelockDelay {
+        delay_sec: u64,
+    },
+    /// Sets delay between staging and deploying code.
+    SetUpgradeDelay {
+        delay_sec: u64,
+    },
+    /// Sets wNEAR contract used by `near_deposit` and `withdraw_near`.
+    SetWnearId {
+        wnear_id: ValidAccountId,
+    },
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub enum ProposalStatus {
+    Pending,
+    Executed,
+    Vetoed,
+    Cancelled,
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct AdminProposal {
+    pub action: AdminAction,
+    /// Time (in seconds) of the proposal.
+    pub proposed_at: u64,
+    /// Time (in seconds) from which the proposal can be executed.
+    pub executable_at: u64,
+    pub status: ProposalStatus,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct AdminProposalInfo {
+    pub proposal_id: u64,
+    pub action: AdminAction,
+    pub proposed_at: u64,
+    pub executable_at: u64,
+    pub status: ProposalStatus,
+}
+
+impl AdminProposalInfo {
+    fn new(proposal_id: u64, proposal: AdminProposal) -> Self {
+        Self {
+            proposal_id,
+            action: proposal.action,
+            proposed_at: proposal.proposed_at,
+            executable_at: proposal.executable_at,
+            status: proposal.status,
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Proposes admin action, executable after the timelock delay. Only can be called by owner.
+    /// Returns id of the proposal.
+    #[payable]
+    pub fn propose_admin_action(&mut self, action: AdminAction) -> u64 {
+        assert_one_yocto();
+        self.assert_owner();
+        match &action {
+            AdminAction::ModifyAdminFee {
+                exchange_fee,
+                referral_fee,
+            } => assert!(
+                exchange_fee + referral_fee <= FEE_DIVISOR,
+                "{}",
+                ERR101_ILLEGAL_FEE
+            ),
+            AdminAction::RetrieveUnmanagedToken { amount, .. } => {
+                assert!(amount.0 > 0, "{}", ERR29_ILLEGAL_WITHDRAW_AMOUNT)
+            }
+            AdminAction::SetTimelockDelay { delay_sec }
+            | AdminAction::SetUpgradeDelay { delay_sec } => assert!(
+                *delay_sec <= MAX_TIMELOCK_DELAY_SEC,
+                "{}",
+                ERR163_ILLEGAL_TIMELOCK_DELAY
+            ),
+            AdminAction::SetOwner { .. }
+            | AdminAction::ExtendGuardians { .. }
+            | AdminAction::RemoveGuardians { .. }
+            | AdminAction::SetWnearId { .. } => (),
+        }
+        let proposal_id = self.admin_proposals.len();
+        let now = now_sec();
+        let executable_at = now + self.timelock_delay_sec;
+        Event::AdminProposalCreated {
+            proposal_id,
+            action: &action,
+            executable_at,
+        }
+        .emit();
+        self.admin_proposals.push(&AdminProposal {
+            action,
+            proposed_at: now,
+            executable_at,
+            status: ProposalStatus::Pending,
+        });
+        proposal_id
+    }
+
+    /// Executes pending proposal whose timelock passed. Only can be called by owner.
+    #[payable]
+    pub fn execute_admin_action(&mut self, proposal_id: u64) -> PromiseOrValue<()> {
+        assert_one_yocto();
+        self.assert_owner();
+        let proposal = self.internal_close_admin_proposal(proposal_id, ProposalStatus::Executed);
+        assert!(
+            now_sec() >= proposal.executable_at,
+            "{}",
+            ERR162_PROPOSAL_TIMELOCKED
+        );
+        match proposal.action {
+            AdminAction::ModifyAdminFee {
+                exchange_fee,
+                referral_fee,
+            } => {
+                self.exchange_fee = exchange_fee;
+                self.referral_fee = referral_fee;
+                Event::FeeChanged {
+                    exchange_fee,
+                    referral_fee,
+                }
+                .emit();
+            }
+            AdminAction::SetOwner { owner_id } => self.owner_id = owner_id.into(),
+            AdminAction::ExtendGuardians { guardians } => {
+                for guardian in guardians {
+                    self.guardians.insert(guardian.as_ref());
+                }
+            }
+            AdminAction::RemoveGuardians { guardians } => {
+                for guardian in guardians {
+                    self.guardians.remove(guardian.as_ref());
+                }
+            }
+            AdminAction::RetrieveUnmanagedToken { token_id, amount } => {
+                Event::UnmanagedTokenRetrieved {
+                    token_id: token_id.as_ref(),
+                    amount,
+                    receiver_id: &self.owner_id,
+                }
+                .emit();
+                return PromiseOrValue::Promise(ext_fungible_token::ft_transfer(
+                    self.owner_id.clone(),
+                    amount,
+                    None,
+                    token_id.as_ref(),
+                    1,
+                    env::prepaid_gas() - GAS_FOR_BASIC_OP,
+                ));
+            }
+            AdminAction::SetTimelockDelay { delay_sec } => self.timelock_delay_sec = delay_sec,
+            AdminAction::SetUpgradeDelay { delay_sec } => self.upgrade_delay_sec = delay_sec,
+            AdminAction::SetWnearId { wnear_id } => self.wnear_id = Some(wnear_id.into()),
+        }
+        PromiseOrValue::Value(())
+    }
+
+    /// Vetoes pending proposal, other than removal of guardians. Only can be called by guardians.
+    #[payable]
+    pub fn veto_admin_action(&mut self, proposal_id: u64) {
+        assert_one_yocto();
+        assert!(
+            self.guardians.contains(&env::predecessor_account_id()),
+            "{}",
+            ERR100_NOT_ALLOWED
+        );
+        let proposal = self
+            .admin_proposals
+            .get(proposal_id)
+            .expect(ERR160_NO_PROPOSAL);
+        assert!(
+            !matches!(proposal.action, AdminAction::RemoveGuardians { .. }),
+            "{}",
+            ERR164_PROPOSAL_NOT_VETOABLE
+        );
+        self.internal_close_admin_proposal(proposal_id, ProposalStatus::Vetoed);
+    }
+
+    /// Cancels pending proposal. Only can be called by owner.
+    #[payable]
+    pub fn cancel_admin_action(&mut self, proposal_id: u64) {
+        assert_one_yocto();
+        self.assert_owner();
+        self.internal_close_admin_proposal(proposal_id, ProposalStatus::Cancelled);
+    }
+
+    /// Returns delay (in seconds) between proposal and execution of admin actions.
+    pub fn get_timelock_delay(&self) -> u64 {
+        self.timelock_delay_sec
+    }
+
+    pub fn get_number_of_admin_proposals(&self) -> u64 {
+        self.admin_proposals.len()
+    }
+
+    pub fn get_admin_proposal(&self, proposal_id: u64) -> Option<AdminProposalInfo> {
+        self.admin_proposals
+            .get(proposal_id)
+            .map(|proposal| AdminProposalInfo::new(proposal_id, proposal))
+    }
+
+    /// Returns up to `limit` proposals starting from `from_index`.
+    pub fn get_admin_proposals(&self, from_index: u64, limit: u64) -> Vec<AdminProposalInfo> {
+        (from_index..std::cmp::min(from_index + limit, self.admin_proposals.len()))
+            .map(|proposal_id| self.get_admin_proposal(proposal_id).unwrap())
+            .collect()
+    }
+}
+
+impl Contract {
+    /// Moves pending proposal to given final status. Returns the proposal.
+    fn internal_close_admin_proposal(
+        &mut self,
+        proposal_id: u64,
+        status: ProposalStatus,
+    ) -> AdminProposal {
+        let mut proposal = self
+            .admin_proposals
+            .get(proposal_id)
+            .expect(ERR160_NO_PROPOSAL);
+        assert_eq!(
+            proposal.status,
+            ProposalStatus::Pending,
+            "{}",
+            ERR161_PROPOSAL_NOT_PENDING
+        );
+        proposal.status = status;
+        self.admin_proposals.replace(proposal_id, &proposal);
+        Event::AdminProposalClosed {
+            proposal_id,
+            status,
+            closed_by: &env::predecessor_account_id(),
+        }
+        .emit();
+        proposal
+    }
+}
@@ -2,6 +2,7 @@ use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
 use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::{serde_json, PromiseOrValue};
 
+use crate::utils::{uint_sqrt, FEE_DIVISOR, U256};
 use crate::*;
 
 pub const VIRTUAL_ACC: &str = "@";
@@ -17,6 +18,30 @@ enum TokenReceiverMessage {
         /// List of sequential actions.
         actions: Vec<Action>,
     },
+    /// Repayment of outstanding flash loan of given pool.
+    RepayFlashLoan { repay_flash_loan: u64 },
+    /// Adds liquidity to given two token simple pool with received token only,
+    /// swapping part of it for the other token. Sender has to be registered for the pool shares.
+    AddLiquidity { pool_id: u64, min_shares: U128 },
+    /// Deposit to another registered account.
+    Deposit { account_id: ValidAccountId },
+}
+
+/// Returns amount of `amount` to swap in a constant product pool with `reserve_in` and `fee`,
+/// so that the rest and the swap output are in the pool ratio after the swap.
+/// Solves `(1 - f) * s ^ 2 + (2 - f) * r * s - a * r = 0` for `s`.
+fn zap_swap_amount(reserve_in: Balance, amount: Balance, fee: u32) -> Balance {
+    let k = U256::from(FEE_DIVISOR - fee);
+    let m = U256::from(2 * FEE_DIVISOR - fee) * U256::from(reserve_in);
+    m.checked_mul(m)
+        .and_then(|m2| {
+            (k * U256::from(FEE_DIVISOR) * U256::from(amount))
+                .checked_mul(U256::from(reserve_in) * U256::from(4))
+                .and_then(|d| m2.checked_add(d))
+        })
+        .map(|discriminant| ((uint_sqrt(discriminant) - m) / (k * U256::from(2))).as_u128())
+        // Half is close enough for reserves too large to solve for.
+        .unwrap_or(amount / 2)
 }
 
 impl Contract {
@@ -24,6 +49,7 @@ impl Contract {
     /// Returns amounts to send to the sender directly.
     fn internal_direct_actions(
         &mut self,
+        sender_id: &AccountId,
         token_in: AccountId,
         amount_in: Balance,
         referral_id: Option<AccountId>,
@@ -34,9 +60,10 @@ impl Contract {
 
         account.deposit(&token_in, amount_in);
         let _ = self.internal_execute_actions(
+            sender_id,
             &mut account,
             &referral_id,
-            &actions,
+            actions,
             ActionResult::Amount(U128(amount_in)),
         );
 
@@ -50,6 +77,81 @@ impl Contract {
 
         result
     }
+
+    /// Swaps part of `amount_in` of `token_in` in given pool and adds it with the rest as liquidity of the sender.
+    /// Leftover of the other token is sent back. Returns unused amount of `token_in`.
+    fn internal_zap_liquidity(
+        &mut self,
+        sender_id: &AccountId,
+        token_in: &AccountId,
+        amount_in: Balance,
+        pool_id: u64,
+        min_shares: Balance,
+    ) -> Balance {
+        self.assert_no_flash_loan(pool_id);
+        self.assert_pool_migrated(pool_id);
+        self.assert_pool_running(pool_id);
+        let mut pool = self.internal_get_pool(pool_id).expect(ERR85_NO_POOL);
+        let reserves = match &pool {
+            Pool::SimplePool(simple_pool) if simple_pool.amounts.len() == 2 => {
+                simple_pool.amounts.clone()
+            }
+            _ => env::panic(ERR35_ZAP_UNSUPPORTED_POOL.as_bytes()),
+        };
+        assert!(pool.is_lp(sender_id), "{}", ERR13_LP_NOT_REGISTERED);
+        let in_idx = pool
+            .tokens()
+            .iter()
+            .position(|token| token == token_in)
+            .expect(ERR63_MISSING_TOKEN);
+        let out_idx = 1 - in_idx;
+        let token_out = pool.tokens()[out_idx].clone();
+        let exchange_id = env::current_account_id();
+        self.internal_checkpoint_farmers(pool_id, &pool, &[sender_id, &exchange_id]);
+
+        let swap_amount = zap_swap_amount(reserves[in_idx], amount_in, pool.get_fee());
+        let amount_out = pool.swap(
+            token_in,
+            swap_amount,
+            &token_out,
+            0,
+            AdminFees {
+                exchange_fee: self.exchange_fee,
+                exchange_id,
+                referral_fee: 0,
+                referral_id: None,
+            },
+        );
+        Event::Swap {
+            account_id: sender_id,
+            pool_id,
+            token_in,
+            amount_in: U128(swap_amount),
+            token_out: &token_out,
+            amount_out: U128(amount_out),
+        }
+        .emit();
+
+        let mut amounts = vec![0; 2];
+        amounts[in_idx] = amount_in - swap_amount;
+        amounts[out_idx] = amount_out;
+        let shares = pool.add_liquidity(sender_id, &mut amounts);
+        assert!(shares >= min_shares, "{}", ERR68_SLIPPAGE);
+        Event::AddLiquidity {
+            account_id: sender_id,
+            pool_id,
+            amounts: amounts.iter().map(|amount| U128(*amount)).collect(),
+            shares: U128(shares),
+        }
+        .emit();
+        self.internal_auto_fill_limit_orders(pool_id, &mut pool);
+        self.internal_save_pool(pool_id, pool);
+
+        if amount_out > amounts[out_idx] {
+            self.internal_send_tokens(sender_id, &token_out, amount_out - amounts[out_idx]);
+        }
+        amount_in - swap_amount - amounts[in_idx]
+    }
 }
 
 #[near_bindgen]
@@ -79,12 +181,48 @@ impl FungibleTokenReceiver for Contract {
                     actions,
                 } => {
                     let referral_id = referral_id.map(|x| x.to_string());
-                    let out_amounts =
-                        self.internal_direct_actions(token_in, amount.0, referral_id, &actions);
-                    for (token_out, amount_out) in out_amounts.into_iter() {
-                        self.internal_send_tokens(sender_id.as_ref(), &token_out, amount_out);
+                    let out_amounts = self.internal_direct_actions(
+                        sender_id.as_ref(),
+                        token_in.clone(),
+                        amount.0,
+                        referral_id,
+                        &actions,
+                    );
+                    // Unspent token_in is refunded by the token contract, up to the received amount.
+                    let refund = out_amounts
+                        .iter()
+                        .find(|(token_out, _)| token_out == &token_in)
+                        .map_or(0, |(_, amount_out)| std::cmp::min(*amount_out, amount.0));
+                    for (token_out, mut amount_out) in out_amounts.into_iter() {
+                        if token_out == token_in {
+                            amount_out -= refund;
+                        }
+                        // Failed transfers are credited back to sender's deposits or to lost and found.
+                        if amount_out > 0 {
+                            self.internal_send_tokens(sender_id.as_ref(), &token_out, amount_out);
+                        }
                     }
-                    // Even if send tokens fails, we don't return funds back to sender.
+                    PromiseOrValue::Value(U128(refund))
+                }
+                TokenReceiverMessage::RepayFlashLoan { repay_flash_loan } => {
+                    self.internal_repay_flash_loan(repay_flash_loan, &token_in, amount.0);
+                    PromiseOrValue::Value(U128(0))
+                }
+                TokenReceiverMessage::AddLiquidity {
+                    pool_id,
+                    min_shares,
+                } => {
+                    let unused = self.internal_zap_liquidity(
+                        sender_id.as_ref(),
+                        &token_in,
+                        amount.0,
+                        pool_id,
+                        min_shares.0,
+                    );
+                    PromiseOrValue::Value(U128(unused))
+                }
+                TokenReceiverMessage::Deposit { account_id } => {
+                    self.internal_deposit(account_id.as_ref(), &token_in, amount.0);
                     PromiseOrValue::Value(U128(0))
                 }
             }
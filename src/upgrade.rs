@@ -0,0 +1,122 @@
+//! Upgrade of the contract by itself.
+//!
+//! Upgraders stage new wasm code, passed as raw input of `stage_code`, so DAOs can stage it with
+//! a single function call. Once `upgrade_delay_sec` passed since staging, `deploy_staged_code`
+//! deploys it and calls `migrate` of the new code in one batch, which fails together with the
+//! migration. Staged code is kept until the batch succeeded, so a failed one can be retried.
+//! Staging another code restarts the delay. Until deployed, guardians can remove staged code.
+
+use std::convert::TryInto;
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::Base58CryptoHash;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::Gas;
+
+use crate::roles::Role;
+use crate::utils::{ext_self, now_sec, GAS_FOR_BASIC_OP};
+use crate::*;
+
+/// Gas kept for finishing `deploy_staged_code` and its callback, the rest of prepaid gas goes to `migrate`.
+const GAS_FOR_DEPLOY_STAGED_CODE: Gas = 20_000_000_000_000 + GAS_FOR_BASIC_OP;
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct StagedUpgrade {
+    /// Sha256 hash of the staged code.
+    pub code_hash: Base58CryptoHash,
+    /// Time (in seconds) from which the code can be deployed.
+    pub deployable_at: u64,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Stages wasm code given as raw input, replacing code staged before.
+    /// Only can be called by upgraders.
+    pub fn stage_code(&mut self) -> StagedUpgrade {
+        self.assert_role(Role::Upgrader);
+        let code = env::input().expect(ERR170_NO_STAGED_CODE);
+        assert!(!code.is_empty(), "{}", ERR170_NO_STAGED_CODE);
+        let code_hash: [u8; 32] = env::sha256(&code).try_into().unwrap();
+        self.staged_code.set(&code);
+        let staged_upgrade = StagedUpgrade {
+            code_hash: code_hash.into(),
+            deployable_at: now_sec() + self.upgrade_delay_sec,
+        };
+        self.staged_upgrade = Some(staged_upgrade.clone());
+        Event::CodeStaged {
+            code_hash: staged_upgrade.code_hash,
+            deployable_at: staged_upgrade.deployable_at,
+        }
+        .emit();
+        staged_upgrade
+    }
+
+    /// Removes staged code. Only can be called by upgraders and guardians.
+    pub fn remove_staged_code(&mut self) {
+        self.assert_guardian_or_role(Role::Upgrader);
+        assert!(self.staged_code.remove(), "{}", ERR170_NO_STAGED_CODE);
+        let staged_upgrade = self.staged_upgrade.take().unwrap();
+        Event::StagedCodeRemoved {
+            code_hash: staged_upgrade.code_hash,
+            removed_by: &env::predecessor_account_id(),
+        }
+        .emit();
+    }
+
+    /// Deploys staged code and calls `migrate` of it with the rest of prepaid gas.
+    /// Only can be called by upgraders.
+    pub fn deploy_staged_code(&mut self) -> Promise {
+        self.assert_role(Role::Upgrader);
+        let staged_upgrade = self.staged_upgrade.clone().expect(ERR170_NO_STAGED_CODE);
+        assert!(
+            now_sec() >= staged_upgrade.deployable_at,
+            "{}",
+            ERR171_STAGED_CODE_TIMELOCKED
+        );
+        let code = self.staged_code.get().expect(ERR170_NO_STAGED_CODE);
+        Promise::new(env::current_account_id())
+            .deploy_contract(code)
+            .function_call(
+                b"migrate".to_vec(),
+                vec![],
+                0,
+                env::prepaid_gas() - env::used_gas() - GAS_FOR_DEPLOY_STAGED_CODE,
+            )
+            .then(ext_self::callback_deploy_staged_code(
+                staged_upgrade.code_hash,
+                &env::current_account_id(),
+                0,
+                GAS_FOR_BASIC_OP,
+            ))
+    }
+
+    /// Removes staged code once it was deployed and migrated, unless other code was staged since.
+    /// Runs as the new code.
+    #[private]
+    pub fn callback_deploy_staged_code(&mut self, code_hash: Base58CryptoHash) {
+        let deployed = matches!(env::promise_result(0), PromiseResult::Successful(_));
+        if deployed {
+            Event::CodeDeployed { code_hash }.emit();
+        }
+        let staged_hash = self
+            .staged_upgrade
+            .as_ref()
+            .map(|staged_upgrade| staged_upgrade.code_hash);
+        if deployed && staged_hash == Some(code_hash) {
+            self.staged_code.remove();
+            self.staged_upgrade = None;
+        }
+    }
+
+    /// Returns hash of staged code and when it can be deployed.
+    pub fn get_staged_upgrade(&self) -> Option<StagedUpgrade> {
+        self.staged_upgrade.clone()
+    }
+
+    /// Returns delay (in seconds) between staging and deploying code.
+    pub fn get_upgrade_delay(&self) -> u64 {
+        self.upgrade_delay_sec
+    }
+}
@@ -3,24 +3,44 @@ use std::collections::HashSet;
 
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::collections::UnorderedMap;
-use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::json_types::{Base58CryptoHash, ValidAccountId, U128};
 use near_sdk::serde::{Deserialize, Serialize};
-use near_sdk::{ext_contract, AccountId, Balance, Gas};
+use near_sdk::{env, ext_contract, AccountId, Balance, Gas};
 use uint::construct_uint;
 
 use crate::errors::*;
 
-construct_uint! {
-    /// 256-bit unsigned integer.
-    pub struct U256(4);
+#[allow(clippy::assign_op_pattern, clippy::manual_div_ceil)]
+mod uint_types {
+    use super::construct_uint;
+
+    construct_uint! {
+        /// 256-bit unsigned integer.
+        pub struct U256(4);
+    }
+}
+pub use uint_types::U256;
+
+impl BorshSerialize for U256 {
+    fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        BorshSerialize::serialize(&self.0, writer)
+    }
+}
+
+impl BorshDeserialize for U256 {
+    fn deserialize(buf: &mut &[u8]) -> std::io::Result<Self> {
+        Ok(U256(BorshDeserialize::deserialize(buf)?))
+    }
 }
 
 // 10^24 yocto near
 // pub const ONE_NEAR: u128 = 1_000_000_000_000_000_000_000_000;
 
-pub const NUM_TOKENS: usize = 2;
+/// Bounds on number of tokens in a pool.
+pub const MIN_NUM_TOKENS: usize = 2;
+pub const MAX_NUM_TOKENS: usize = 8;
 
-/// Attach no deposit.
+// Attach no deposit.
 // pub const NO_DEPOSIT: u128 = 0;
 
 /// 10T gas for basic operation
@@ -29,7 +49,8 @@ pub const GAS_FOR_BASIC_OP: Gas = 10_000_000_000_000;
 /// hotfix_insuffient_gas_for_mft_resolve_transfer.
 pub const GAS_FOR_RESOLVE_TRANSFER: Gas = 20_000_000_000_000;
 
-// pub const GAS_FOR_FT_TRANSFER_CALL: Gas = 25_000_000_000_000 + GAS_FOR_RESOLVE_TRANSFER;
+/// Gas kept by `mft_transfer_call` for itself and the resolve callback.
+pub const GAS_FOR_FT_TRANSFER_CALL: Gas = 25_000_000_000_000 + GAS_FOR_RESOLVE_TRANSFER;
 
 /// Amount of gas for fungible token transfers, increased to 20T to support AS token contracts.
 pub const GAS_FOR_FT_TRANSFER: Gas = 20_000_000_000_000;
@@ -37,6 +58,9 @@ pub const GAS_FOR_FT_TRANSFER: Gas = 20_000_000_000_000;
 /// Fee divisor, allowing to provide fee in bps.
 pub const FEE_DIVISOR: u32 = 10_000;
 
+/// Fee tiers (in bps) allowed for simple pools at initialization.
+pub const DEFAULT_FEE_TIERS: [u32; 4] = [1, 5, 30, 100];
+
 /// Initial shares supply on deposit of liquidity.
 pub const INIT_SHARES_SUPPLY: u128 = 1_000_000_000_000_000_000_000_000;
 
@@ -51,13 +75,18 @@ pub fn uint_sqrt(value: U256) -> U256 {
     res
 }
 
-pub fn check_duplicate_tokens(tokens: &Vec<ValidAccountId>) {
+/// Current block time in seconds.
+pub fn now_sec() -> u64 {
+    env::block_timestamp() / 1_000_000_000
+}
+
+pub fn check_duplicate_tokens(tokens: &[ValidAccountId]) {
     let token_set: HashSet<_> = tokens.iter().map(|token| token.as_ref()).collect();
     assert_eq!(tokens.len(), token_set.len(), "{}", ERR_DUPLICATE_TOKENS);
 }
 
 pub fn add_to_collection(c: &mut UnorderedMap<AccountId, Balance>, key: &AccountId, value: Balance) {
-    let prev_value = c.get(&key).unwrap_or(0);
+    let prev_value = c.get(key).unwrap_or(0);
     c.insert(key, &(value + prev_value));
 }
 
@@ -86,4 +115,20 @@ pub trait Exchange {
         sender_id: AccountId,
         amount: U128,
     );
+
+    fn mft_resolve_transfer(
+        &mut self,
+        token_id: String,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> U128;
+
+    fn callback_flash_loan(&mut self, pool_id: u64) -> U128;
+
+    fn callback_near_deposit(&mut self, wnear_id: AccountId, account_id: AccountId, amount: U128);
+
+    fn callback_deploy_staged_code(&mut self, code_hash: Base58CryptoHash);
+
+    fn callback_near_withdraw(&mut self, wnear_id: AccountId, account_id: AccountId, amount: U128);
 }
@@ -1,6 +1,6 @@
 // View functions for the contract
 
-use near_sdk::json_types::U128;
+use near_sdk::json_types::{WrappedTimestamp, U128};
 use near_sdk::{
     near_bindgen,
     serde::{Deserialize, Serialize},
@@ -38,24 +38,73 @@ pub struct PoolInfo {
     /// Total number of shares.
     pub shares_total_supply: U128,
     pub amp: u64,
+    /// Normalized token weights, only for weighted pools.
+    pub weights: Option<Vec<u32>>,
+    pub state: PoolState,
 }
 
-impl From<Pool> for PoolInfo {
-    fn from(pool: Pool) -> Self {
+impl PoolInfo {
+    fn new(pool: Pool, state: PoolState) -> Self {
         let pool_kind = pool.kind();
         match pool {
             Pool::SimplePool(pool) => Self {
                 pool_kind,
                 amp: 0,
+                weights: None,
                 token_account_ids: pool.token_account_ids,
-                amounts: pool.amounts.into_iter().map(|a| U128(a)).collect(),
+                amounts: pool.amounts.into_iter().map(U128).collect(),
                 total_fee: pool.total_fee,
                 shares_total_supply: U128(pool.shares_total_supply),
+                state,
+            },
+            Pool::StableSwapPool(pool) => Self {
+                pool_kind,
+                amp: pool.get_amp(),
+                weights: None,
+                token_account_ids: pool.token_account_ids,
+                amounts: pool.amounts.into_iter().map(U128).collect(),
+                total_fee: pool.total_fee,
+                shares_total_supply: U128(pool.shares_total_supply),
+                state,
+            },
+            Pool::WeightedPool(pool) => Self {
+                pool_kind,
+                amp: 0,
+                weights: Some(pool.weights),
+                token_account_ids: pool.token_account_ids,
+                amounts: pool.amounts.into_iter().map(U128).collect(),
+                total_fee: pool.total_fee,
+                shares_total_supply: U128(pool.shares_total_supply),
+                state,
             },
         }
     }
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct StablePoolInfo {
+    /// List of tokens in the pool.
+    pub token_account_ids: Vec<AccountId>,
+    /// Decimals of each token in the pool.
+    pub decimals: Vec<u8>,
+    /// How much of each token the pool has.
+    pub amounts: Vec<U128>,
+    /// Balances normalized to the same decimals, used by the invariant.
+    pub c_amounts: Vec<U128>,
+    /// Fee charged for swap.
+    pub total_fee: u32,
+    /// Total number of shares.
+    pub shares_total_supply: U128,
+    /// Current amplification factor.
+    pub amp: u64,
+    /// Amplification factor the pool is ramping to.
+    pub target_amp: u64,
+    /// Timestamp the ramping ends at, in nanoseconds.
+    pub stop_amp_time: WrappedTimestamp,
+}
+
 #[near_bindgen]
 impl Contract {
     /// Return contract basic info
@@ -95,55 +144,107 @@ impl Contract {
         amount_in: U128,
         token_out: ValidAccountId,
     ) -> U128 {
-        let pool = self.pools.get(pool_id).expect(ERR85_NO_POOL);
+        let pool = self.internal_get_pool(pool_id).expect(ERR85_NO_POOL);
         pool.get_return(token_in.as_ref(), amount_in.into(), token_out.as_ref())
             .into()
     }
 
-    /// Given specific pool, returns amount of token_out recevied swapping amount_in of token_in.
+    /// Given specific pool, returns average amount of token_out per one token_in over last `window_sec` seconds,
+    /// in precision 1e18. Only simple pools keep price history.
+    pub fn get_twap(
+        &self,
+        pool_id: u64,
+        token_in: ValidAccountId,
+        token_out: ValidAccountId,
+        window_sec: u64,
+    ) -> U128 {
+        let pool = self.internal_get_pool(pool_id).expect(ERR85_NO_POOL);
+        pool.get_twap(token_in.as_ref(), token_out.as_ref(), window_sec)
+            .into()
+    }
+
+    /// Given specific pool, returns amount of token_in required to receive amount_out of token_out, fee included.
     pub fn get_income(
         &self,
         pool_id: u64,
         token_in: ValidAccountId,
-        amount_in: U128,
+        amount_out: U128,
         token_out: ValidAccountId,
     ) -> U128 {
-        let pool = self.pools.get(pool_id).expect(ERR85_NO_POOL);
-        pool.get_income(token_in.as_ref(), token_out.as_ref(), amount_in.into())
+        let pool = self.internal_get_pool(pool_id).expect(ERR85_NO_POOL);
+        pool.get_income(token_in.as_ref(), token_out.as_ref(), amount_out.into())
             .into()
     }
 
     /// Get a single pool by given `id`
     pub fn get_pool(&self, pool_id: u64) -> PoolInfo {
-        self.pools.get(pool_id).expect("ERR_POOL_NOT_FOUND").into()
+        PoolInfo::new(
+            self.internal_get_pool(pool_id).expect("ERR_POOL_NOT_FOUND"),
+            self.internal_get_pool_state(pool_id),
+        )
+    }
+
+    /// Get detailed info of a single stable swap pool by given `id`.
+    pub fn get_stable_pool(&self, pool_id: u64) -> StablePoolInfo {
+        match self.internal_get_pool(pool_id).expect(ERR85_NO_POOL) {
+            Pool::StableSwapPool(pool) => StablePoolInfo {
+                amp: pool.get_amp(),
+                c_amounts: pool.get_c_amounts().into_iter().map(U128).collect(),
+                target_amp: pool.target_amp_factor as u64,
+                stop_amp_time: pool.stop_amp_time.into(),
+                token_account_ids: pool.token_account_ids,
+                decimals: pool.token_decimals,
+                amounts: pool.amounts.into_iter().map(U128).collect(),
+                total_fee: pool.total_fee,
+                shares_total_supply: U128(pool.shares_total_supply),
+            },
+            _ => env::panic(ERR86_UNSUPPORTED_POOL_KIND.as_bytes()),
+        }
     }
 
     pub fn get_whitelisted_tokens(&self) -> Vec<AccountId> {
         self.whitelisted_tokens.to_vec()
     }
 
+    /// Return fees (in bps) simple pools can be created with.
+    pub fn get_fee_tiers(&self) -> Vec<u32> {
+        self.fee_tiers.clone()
+    }
+
     /// Return total fee of the given pool.
     pub fn get_pool_fee(&self, pool_id: u64) -> u32 {
-        self.pools.get(pool_id).expect("ERR_NO_POOL").get_fee()
+        self.internal_get_pool(pool_id)
+            .expect("ERR_NO_POOL")
+            .get_fee()
     }
 
     /// Return volumes of the given pool.
     pub fn get_pool_volumes(&self, pool_id: u64) -> Vec<SwapVolume> {
-        self.pools.get(pool_id).expect("ERR_NO_POOL").get_volumes()
+        self.internal_get_pool(pool_id)
+            .expect("ERR_NO_POOL")
+            .get_volumes()
     }
 
+    /// Returns value of one whole share (10^share_decimal) of given pool in units of its first token.
     pub fn get_pool_share_price(&self, pool_id: u64) -> U128 {
-        self.pools
-            .get(pool_id)
+        self.internal_get_pool(pool_id)
             .expect("ERR_NO_POOL")
             .get_share_price()
             .into()
     }
 
+    /// Returns amounts of each token backing one whole share (10^share_decimal) of given pool.
+    pub fn get_pool_share_breakdown(&self, pool_id: u64) -> Vec<U128> {
+        let pool = self.internal_get_pool(pool_id).expect("ERR_NO_POOL");
+        pool.predict_remove_liquidity(10u128.pow(pool.get_share_decimal() as u32))
+            .into_iter()
+            .map(U128)
+            .collect()
+    }
+
     /// Return number of shares given account has in given pool
     pub fn get_account_shares_in_pool(&self, pool_id: u64, account_id: ValidAccountId) -> U128 {
-        self.pools
-            .get(pool_id)
+        self.internal_get_pool(pool_id)
             .expect("ERR_NO_POOL")
             .share_balances(account_id.as_ref())
             .into()
@@ -151,8 +252,7 @@ impl Contract {
 
     /// Returns total number of shares in the given pool.
     pub fn get_pool_total_shares(&self, pool_id: u64) -> U128 {
-        self.pools
-            .get(pool_id)
+        self.internal_get_pool(pool_id)
             .expect("ERR_NO_POOL")
             .share_total_balance()
             .into()
@@ -164,7 +264,7 @@ impl Contract {
             .internal_unwrap_account(account_id)
             .tokens
             .iter()
-            .map(|(token, amount)| token.clone())
+            .map(|(token, _amount)| token.clone())
             .collect();
         tokens
     }
@@ -178,10 +278,10 @@ impl Contract {
     // }
 
     pub fn predict_remove_liquidity(&self, pool_id: u64, shares: U128) -> Vec<U128> {
-        let pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
+        let pool = self.internal_get_pool(pool_id).expect("ERR_NO_POOL");
         pool.predict_remove_liquidity(shares.into())
             .into_iter()
-            .map(|x| U128(x))
+            .map(U128)
             .collect()
     }
 
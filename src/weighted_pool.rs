@@ -0,0 +1,487 @@
+use std::cmp::min;
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::UnorderedMap;
+use near_sdk::json_types::ValidAccountId;
+use near_sdk::{env, AccountId, Balance};
+
+use crate::admin_fee::AdminFees;
+use crate::errors::*;
+use crate::utils::{
+    add_to_collection, uint_sqrt, SwapVolume, FEE_DIVISOR, INIT_SHARES_SUPPLY, MAX_NUM_TOKENS,
+    MIN_NUM_TOKENS, U256,
+};
+use crate::StorageKey;
+
+/// Weights of all tokens in a pool sum up to this value.
+pub const WEIGHT_DIVISOR: u32 = 10_000;
+/// Minimal weight of a single token, 1%.
+pub const MIN_WEIGHT: u32 = 100;
+/// A single swap can not take in or give out more than this fraction of the pool balance (in bps).
+const MAX_IN_RATIO: u128 = 3_000;
+const MAX_OUT_RATIO: u128 = 3_000;
+
+/// 1.0 in fixed point arithmetic used for weighted math.
+const ONE: u128 = 1_000_000_000_000_000_000;
+/// Bits of the fractional exponent resolved by `pow_up`.
+const POW_FRACTION_BITS: u32 = 48;
+/// Upper bound of relative error of `pow_up`, added on top of its result.
+const MAX_POW_RELATIVE_ERROR: u128 = 100_000_000;
+
+/// Fixed point division rounding up.
+fn div_up(a: U256, b: U256) -> U256 {
+    if a.is_zero() {
+        U256::zero()
+    } else {
+        (a - U256::one()) / b + U256::one()
+    }
+}
+
+/// Fixed point square root of `x`.
+fn sqrt_fixed(x: U256) -> U256 {
+    uint_sqrt(x * U256::from(ONE))
+}
+
+/// Computes `base ^ (exp_num / exp_den)` in fixed point, rounding the result up.
+/// Integer part of exponent is resolved by multiplication, fractional part by repeated square roots.
+fn pow_up(base: U256, exp_num: u32, exp_den: u32) -> U256 {
+    let one = U256::from(ONE);
+    let mut result = one;
+    for _ in 0..exp_num / exp_den {
+        result = result * base / one;
+    }
+    let mut remainder = (exp_num % exp_den) as u64;
+    let mut root = base;
+    for _ in 0..POW_FRACTION_BITS {
+        if remainder == 0 {
+            break;
+        }
+        root = sqrt_fixed(root);
+        remainder *= 2;
+        if remainder >= exp_den as u64 {
+            remainder -= exp_den as u64;
+            result = result * root / one;
+        }
+    }
+    result + result * U256::from(MAX_POW_RELATIVE_ERROR) / one + U256::one()
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct WeightedPool {
+    /// List of tokens in the pool.
+    pub token_account_ids: Vec<AccountId>,
+    /// Normalized weight of each token, sum of them is WEIGHT_DIVISOR.
+    pub weights: Vec<u32>,
+    /// How much of each token this pool has.
+    pub amounts: Vec<Balance>,
+    /// Volumes accumulated by this pool.
+    pub volumes: Vec<SwapVolume>,
+    /// Fee charged for swap (gets divided by FEE_DIVISOR).
+    pub total_fee: u32,
+    /// Shares of the pool by liquidity providers.
+    pub shares: UnorderedMap<AccountId, Balance>,
+    /// Total number of shares.
+    pub shares_total_supply: Balance,
+}
+
+impl WeightedPool {
+    pub fn new(
+        id: u32,
+        token_account_ids: Vec<ValidAccountId>,
+        weights: Vec<u32>,
+        total_fee: u32,
+    ) -> Self {
+        assert!(total_fee < FEE_DIVISOR, "{}", ERR62_FEE_ILLEGAL);
+        assert!(
+            (MIN_NUM_TOKENS..=MAX_NUM_TOKENS).contains(&token_account_ids.len()),
+            "{}",
+            ERR64_TOKENS_COUNT_ILLEGAL
+        );
+        assert_eq!(
+            weights.len(),
+            token_account_ids.len(),
+            "{}",
+            ERR72_WEIGHT_ILLEGAL
+        );
+        assert!(
+            weights.iter().all(|weight| *weight >= MIN_WEIGHT)
+                && weights.iter().sum::<u32>() == WEIGHT_DIVISOR,
+            "{}",
+            ERR72_WEIGHT_ILLEGAL
+        );
+        Self {
+            token_account_ids: token_account_ids.iter().map(|a| a.clone().into()).collect(),
+            weights,
+            amounts: vec![0u128; token_account_ids.len()],
+            volumes: vec![SwapVolume::default(); token_account_ids.len()],
+            total_fee,
+            shares: UnorderedMap::new(StorageKey::Shares { pool_id: id }),
+            shares_total_supply: 0,
+        }
+    }
+
+    /// Register given account with 0 balance in shares.
+    /// Storage payment should be checked by caller.
+    pub fn share_register(&mut self, account_id: &AccountId) {
+        if self.shares.get(account_id).is_some() {
+            env::panic(ERR14_LP_ALREADY_REGISTERED.as_bytes());
+        }
+        self.shares.insert(account_id, &0);
+    }
+
+    /// Returns balance of shares for given user.
+    pub fn share_balance_of(&self, account_id: &AccountId) -> Balance {
+        self.shares.get(account_id).unwrap_or_default()
+    }
+
+    /// Transfers shares from predecessor to receiver.
+    /// Receiver has to be registered, so no storage is allocated here.
+    pub fn share_transfer(&mut self, sender_id: &AccountId, receiver_id: &AccountId, amount: u128) {
+        let balance = self.shares.get(sender_id).expect(ERR13_LP_NOT_REGISTERED);
+        if let Some(new_balance) = balance.checked_sub(amount) {
+            self.shares.insert(sender_id, &new_balance);
+        } else {
+            env::panic(ERR34_INSUFFICIENT_LP_SHARES.as_bytes());
+        }
+        let balance_out = self
+            .shares
+            .get(receiver_id)
+            .expect(ERR13_LP_NOT_REGISTERED);
+        self.shares.insert(receiver_id, &(balance_out + amount));
+    }
+
+    /// Returns total number of shares in this pool.
+    pub fn share_total_balance(&self) -> Balance {
+        self.shares_total_supply
+    }
+
+    /// Returns list of tokens in this pool.
+    pub fn tokens(&self) -> &[AccountId] {
+        &self.token_account_ids
+    }
+
+    pub fn is_lp(&self, account_id: &AccountId) -> bool {
+        self.shares.get(account_id).is_some()
+    }
+
+    pub fn get_fee(&self) -> u32 {
+        self.total_fee
+    }
+
+    /// Returns volumes of the given pool.
+    pub fn get_volumes(&self) -> Vec<SwapVolume> {
+        self.volumes.clone()
+    }
+
+    // Returns index of token in given pool
+    fn token_index(&self, token_id: &AccountId) -> usize {
+        self.token_account_ids
+            .iter()
+            .position(|id| id == token_id)
+            .expect(ERR63_MISSING_TOKEN)
+    }
+
+    pub fn mint_shares(&mut self, account_id: &AccountId, shares: Balance) {
+        if shares == 0 {
+            return;
+        }
+        self.shares_total_supply += shares;
+        add_to_collection(&mut self.shares, account_id, shares);
+    }
+
+    /// Adds the amounts of tokens to liquidity pool and returns number of shares that this user receives.
+    /// Liquidity is taken in proportion of current balances, so the invariant grows with the share supply.
+    /// Updates amount to amount kept in the pool.
+    pub fn add_liquidity(&mut self, sender_id: &AccountId, amounts: &mut [Balance]) -> Balance {
+        assert_eq!(
+            amounts.len(),
+            self.token_account_ids.len(),
+            "ERR_WRONG_TOKEN_COUNT"
+        );
+        let shares = if self.shares_total_supply > 0 {
+            let mut fair_supply = U256::max_value();
+            for (amount, pool_amount) in amounts.iter().zip(self.amounts.iter()) {
+                assert!(*amount > 0, "{}", ERR31_ZERO_AMOUNT);
+                fair_supply = min(
+                    fair_supply,
+                    U256::from(*amount) * U256::from(self.shares_total_supply) / *pool_amount,
+                );
+            }
+            for (amount, pool_amount) in amounts.iter_mut().zip(self.amounts.iter_mut()) {
+                let balanced = div_up(
+                    U256::from(*pool_amount) * fair_supply,
+                    U256::from(self.shares_total_supply),
+                )
+                .as_u128();
+                assert!(balanced > 0, "{}", ERR31_ZERO_AMOUNT);
+                *pool_amount += balanced;
+                *amount = balanced;
+            }
+            fair_supply.as_u128()
+        } else {
+            for (amount, pool_amount) in amounts.iter().zip(self.amounts.iter_mut()) {
+                assert!(*amount > 0, "{}", ERR65_INIT_TOKEN_BALANCE);
+                *pool_amount += *amount;
+            }
+            INIT_SHARES_SUPPLY
+        };
+        self.mint_shares(sender_id, shares);
+        assert!(shares > 0, "{}", ERR32_ZERO_SHARES);
+
+        shares
+    }
+
+    pub fn remove_liquidity(
+        &mut self,
+        sender_id: &AccountId,
+        shares: Balance,
+        min_amounts: Vec<Balance>,
+    ) -> Vec<Balance> {
+        assert_eq!(
+            min_amounts.len(),
+            self.token_account_ids.len(),
+            "ERR_WRONG_TOKEN_COUNT"
+        );
+        let prev_shares_amount = self.shares.get(sender_id).expect("ERR_NO_SHARES");
+        assert!(prev_shares_amount >= shares, "ERR_NOT_ENOUGH_SHARES");
+        let amounts = self.predict_remove_liquidity(shares);
+        for ((pool_amount, amount), min_amount) in self
+            .amounts
+            .iter_mut()
+            .zip(amounts.iter())
+            .zip(min_amounts.iter())
+        {
+            assert!(amount >= min_amount, "ERR_MIN_AMOUNT");
+            *pool_amount -= *amount;
+        }
+        // Never unregister a LP when he removed all his liquidity.
+        self.shares
+            .insert(sender_id, &(prev_shares_amount - shares));
+        self.shares_total_supply -= shares;
+        amounts
+    }
+
+    pub fn predict_remove_liquidity(&self, shares: Balance) -> Vec<Balance> {
+        self.amounts
+            .iter()
+            .map(|amount| {
+                U256::from(*amount)
+                    .checked_mul(shares.into())
+                    .unwrap()
+                    .checked_div(self.shares_total_supply.into())
+                    .unwrap_or_default()
+                    .as_u128()
+            })
+            .collect()
+    }
+
+    /// Returns value of given shares in units of the first token.
+    /// Value held in each token is proportional to its weight.
+    pub fn get_shares_value(&self, shares: Balance) -> u128 {
+        if self.shares_total_supply == 0 {
+            return 0;
+        }
+        (U256::from(self.amounts[0]) * U256::from(WEIGHT_DIVISOR) * U256::from(shares)
+            / U256::from(self.weights[0])
+            / U256::from(self.shares_total_supply))
+        .as_u128()
+    }
+
+    /// Returns number of tokens in outcome, given amount.
+    /// out = balance_out * (1 - (balance_in / (balance_in + amount_in_after_fee)) ^ (weight_in / weight_out))
+    fn internal_get_return(
+        &self,
+        token_in: usize,
+        amount_in: Balance,
+        token_out: usize,
+    ) -> Balance {
+        let in_balance = U256::from(self.amounts[token_in]);
+        let out_balance = U256::from(self.amounts[token_out]);
+        assert!(
+            in_balance > U256::zero()
+                && out_balance > U256::zero()
+                && token_in != token_out
+                && amount_in > 0,
+            "ERR_INVALID"
+        );
+        self.internal_try_get_return(token_in, amount_in, token_out)
+            .expect(ERR73_MAX_RATIO)
+    }
+
+    /// Returns None if `amount_in` is above `MAX_IN_RATIO` of the balance of `token_in`
+    /// or the outcome is above `MAX_OUT_RATIO` of the balance of `token_out`.
+    fn internal_try_get_return(
+        &self,
+        token_in: usize,
+        amount_in: Balance,
+        token_out: usize,
+    ) -> Option<Balance> {
+        let in_balance = U256::from(self.amounts[token_in]);
+        let out_balance = U256::from(self.amounts[token_out]);
+        if U256::from(amount_in) * U256::from(FEE_DIVISOR) > in_balance * U256::from(MAX_IN_RATIO) {
+            return None;
+        }
+
+        let amount_with_fee = U256::from(amount_in) * U256::from(FEE_DIVISOR - self.total_fee)
+            / U256::from(FEE_DIVISOR);
+        let base = div_up(in_balance * U256::from(ONE), in_balance + amount_with_fee);
+        let power = pow_up(base, self.weights[token_in], self.weights[token_out]);
+        if power >= U256::from(ONE) {
+            return Some(0);
+        }
+        let amount_out = out_balance * (U256::from(ONE) - power) / U256::from(ONE);
+        if amount_out * U256::from(FEE_DIVISOR) > out_balance * U256::from(MAX_OUT_RATIO) {
+            return None;
+        }
+        Some(amount_out.as_u128())
+    }
+
+    /// Returns number of tokens in income, given amount of outcome.
+    /// in = balance_in * ((balance_out / (balance_out - amount_out)) ^ (weight_out / weight_in) - 1) / (1 - fee)
+    fn internal_get_income(
+        &self,
+        token_in: usize,
+        token_out: usize,
+        amount_out: Balance,
+    ) -> Balance {
+        let in_balance = U256::from(self.amounts[token_in]);
+        let out_balance = U256::from(self.amounts[token_out]);
+        assert!(
+            in_balance > U256::zero()
+                && out_balance > U256::zero()
+                && token_in != token_out
+                && amount_out > 0,
+            "ERR_INVALID"
+        );
+        assert!(
+            U256::from(amount_out) * U256::from(FEE_DIVISOR)
+                <= out_balance * U256::from(MAX_OUT_RATIO),
+            "{}",
+            ERR73_MAX_RATIO
+        );
+
+        let base = div_up(
+            out_balance * U256::from(ONE),
+            out_balance - U256::from(amount_out),
+        );
+        let power = pow_up(base, self.weights[token_out], self.weights[token_in]);
+        let amount_with_fee = div_up(in_balance * (power - U256::from(ONE)), U256::from(ONE));
+        div_up(
+            amount_with_fee * U256::from(FEE_DIVISOR),
+            U256::from(FEE_DIVISOR - self.total_fee),
+        )
+        .as_u128()
+    }
+
+    pub fn get_return(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> Balance {
+        self.internal_get_return(
+            self.token_index(token_in),
+            amount_in,
+            self.token_index(token_out),
+        )
+    }
+
+    /// Returns None instead of failing for empty reserves or amounts above the max ratios.
+    pub fn try_get_return(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> Option<Balance> {
+        let (in_idx, out_idx) = (self.token_index(token_in), self.token_index(token_out));
+        if self.amounts[in_idx] == 0 || self.amounts[out_idx] == 0 {
+            return None;
+        }
+        self.internal_try_get_return(in_idx, amount_in, out_idx)
+    }
+
+    pub fn get_income(
+        &self,
+        token_in: &AccountId,
+        token_out: &AccountId,
+        amount_out: Balance,
+    ) -> Balance {
+        self.internal_get_income(
+            self.token_index(token_in),
+            self.token_index(token_out),
+            amount_out,
+        )
+    }
+
+    /// Swap `amount_in` of `token_in` token into `token_out` and return how much was received.
+    /// Assuming that `amount_in` was already received from `sender_id`.
+    pub fn swap(
+        &mut self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+        min_amount_out: Balance,
+        admin_fee: &AdminFees,
+    ) -> Balance {
+        assert_ne!(token_in, token_out, "ERR_SAME_TOKEN_SWAP");
+        let in_idx = self.token_index(token_in);
+        let out_idx = self.token_index(token_out);
+        let amount_out = self.internal_get_return(in_idx, amount_in, out_idx);
+        assert!(amount_out > 0, "{}", ERR31_ZERO_AMOUNT);
+        assert!(amount_out >= min_amount_out, "ERR_MIN_AMOUNT");
+
+        self.amounts[in_idx] += amount_in;
+        self.amounts[out_idx] -= amount_out;
+
+        // Swap fee stays in the pool and grows the invariant by roughly
+        // `weight_in * fee_amount / balance_in`, admin fees are a fraction of this growth.
+        let fee_amount =
+            U256::from(amount_in) * U256::from(self.total_fee) / U256::from(FEE_DIVISOR);
+        let numerator =
+            fee_amount * U256::from(self.weights[in_idx]) * U256::from(self.shares_total_supply);
+        let invariant_denominator =
+            U256::from(self.amounts[in_idx]) * U256::from(WEIGHT_DIVISOR) * U256::from(FEE_DIVISOR);
+
+        if admin_fee.exchange_fee > 0 && numerator > U256::zero() {
+            let shares = numerator * U256::from(admin_fee.exchange_fee) / invariant_denominator;
+            self.mint_shares(&admin_fee.exchange_id, shares.as_u128());
+        }
+
+        // If there is referral provided and the account already registered LP, allocate it * of LP rewards.
+        if let Some(referral_id) = &admin_fee.referral_id {
+            if admin_fee.referral_fee > 0
+                && numerator > U256::zero()
+                && self.shares.get(referral_id).is_some()
+            {
+                let shares = numerator * U256::from(admin_fee.referral_fee) / invariant_denominator;
+                self.mint_shares(referral_id, shares.as_u128());
+            }
+        }
+
+        self.volumes[in_idx].input.0 += amount_in;
+        self.volumes[in_idx].output.0 += amount_out;
+
+        amount_out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_f64(value: U256) -> f64 {
+        value.as_u128() as f64 / ONE as f64
+    }
+
+    #[test]
+    fn test_pow_up() {
+        let base = U256::from(ONE / 2);
+        assert!((to_f64(pow_up(base, 2, 1)) - 0.25).abs() < 1e-9);
+        assert!((to_f64(pow_up(base, 1, 4)) - 0.5f64.powf(0.25)).abs() < 1e-9);
+        let base = U256::from(3 * ONE / 2);
+        assert!((to_f64(pow_up(base, 8000, 2000)) - 1.5f64.powi(4)).abs() < 1e-9);
+        assert!((to_f64(pow_up(base, 2000, 8000)) - 1.5f64.powf(0.25)).abs() < 1e-9);
+        // Result never rounds down.
+        assert!(pow_up(base, 3, 1) > U256::from(27 * ONE / 8));
+    }
+}
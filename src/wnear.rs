@@ -0,0 +1,132 @@
+//! Deposits and withdrawals of native NEAR, wrapped into the wNEAR token.
+//!
+//! Attached NEAR is wrapped with `near_deposit` of the wNEAR contract, which mints it to this contract,
+//! and credited to the sender's deposits once that succeeds. Withdrawn wNEAR is unwrapped with
+//! `near_withdraw` and sent to the sender as NEAR. Failed calls are reverted in the callbacks.
+//! The wNEAR contract is set by a time-locked admin action, callbacks get the one that was called.
+
+use near_sdk::ext_contract;
+
+use crate::utils::{ext_self, GAS_FOR_BASIC_OP, GAS_FOR_FT_TRANSFER, GAS_FOR_RESOLVE_TRANSFER};
+use crate::*;
+
+#[ext_contract(ext_wrap_near)]
+pub trait WrapNear {
+    fn near_deposit(&mut self);
+
+    fn near_withdraw(&mut self, amount: U128);
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Wraps attached NEAR and deposits it as wNEAR to the predecessor, who has to be registered.
+    #[payable]
+    pub fn near_deposit(&mut self) -> Promise {
+        self.assert_contract_running();
+        let wnear_id = self.wnear_id.clone().expect(ERR150_WNEAR_NOT_SET);
+        let amount = env::attached_deposit();
+        assert!(amount > 0, "{}", ERR31_ZERO_AMOUNT);
+        let sender_id = env::predecessor_account_id();
+        // Fail before wrapping, the callback can only keep wNEAR of unknown accounts as lostfound.
+        self.internal_unwrap_account(&sender_id);
+        ext_wrap_near::near_deposit(&wnear_id, amount, GAS_FOR_BASIC_OP).then(
+            ext_self::callback_near_deposit(
+                wnear_id.clone(),
+                sender_id,
+                U128(amount),
+                &env::current_account_id(),
+                0,
+                GAS_FOR_RESOLVE_TRANSFER,
+            ),
+        )
+    }
+
+    /// Withdraws given amount of wNEAR from the predecessor's deposits and sends it unwrapped as NEAR.
+    /// a zero amount means to withdraw all.
+    #[payable]
+    pub fn withdraw_near(&mut self, amount: U128) -> Promise {
+        assert_one_yocto();
+        let wnear_id = self.wnear_id.clone().expect(ERR150_WNEAR_NOT_SET);
+        let sender_id = env::predecessor_account_id();
+        let mut account = self.internal_unwrap_account(&sender_id);
+        let mut amount = amount.0;
+        if amount == 0 {
+            amount = account.get_balance(&wnear_id).expect(ERR21_TOKEN_NOT_REG);
+        }
+        assert!(amount > 0, "{}", ERR29_ILLEGAL_WITHDRAW_AMOUNT);
+        account.withdraw(&wnear_id, amount);
+        self.internal_save_account(&sender_id, account);
+        Event::Withdraw {
+            account_id: &sender_id,
+            token_id: &wnear_id,
+            amount: U128(amount),
+        }
+        .emit();
+        ext_wrap_near::near_withdraw(U128(amount), &wnear_id, 1, GAS_FOR_FT_TRANSFER).then(
+            ext_self::callback_near_withdraw(
+                wnear_id.clone(),
+                sender_id,
+                U128(amount),
+                &env::current_account_id(),
+                0,
+                GAS_FOR_RESOLVE_TRANSFER,
+            ),
+        )
+    }
+
+    /// Credits wrapped NEAR to the account, or refunds the NEAR if wrapping failed.
+    #[private]
+    pub fn callback_near_deposit(
+        &mut self,
+        wnear_id: AccountId,
+        account_id: AccountId,
+        amount: U128,
+    ) {
+        match env::promise_result(0) {
+            PromiseResult::NotReady => unreachable!(),
+            PromiseResult::Successful(_) => {
+                // Lostfound event is emitted instead, if not deposited.
+                if self.internal_deposit_or_lostfound(&account_id, &wnear_id, amount.0) {
+                    Event::Deposit {
+                        account_id: &account_id,
+                        token_id: &wnear_id,
+                        amount,
+                    }
+                    .emit();
+                }
+            }
+            PromiseResult::Failed => {
+                Promise::new(account_id).transfer(amount.0);
+            }
+        }
+    }
+
+    /// Sends unwrapped NEAR to the account, or returns the wNEAR to its deposits if unwrapping failed.
+    #[private]
+    pub fn callback_near_withdraw(
+        &mut self,
+        wnear_id: AccountId,
+        account_id: AccountId,
+        amount: U128,
+    ) {
+        match env::promise_result(0) {
+            PromiseResult::NotReady => unreachable!(),
+            PromiseResult::Successful(_) => {
+                Promise::new(account_id).transfer(amount.0);
+            }
+            PromiseResult::Failed => {
+                Event::WithdrawFailed {
+                    account_id: &account_id,
+                    token_id: &wnear_id,
+                    amount,
+                }
+                .emit();
+                self.internal_deposit_or_lostfound(&account_id, &wnear_id, amount.0);
+            }
+        }
+    }
+
+    pub fn get_wnear_id(&self) -> Option<AccountId> {
+        self.wnear_id.clone()
+    }
+}